@@ -0,0 +1,374 @@
+//! Carbon emissions of the attributed energy.
+//!
+//! The grid carbon intensity, in gCO₂e/kWh, is either a static value, the yearly average
+//! of a country or region from the bundled [`REGIONS`] table, or a time series read from
+//! a CSV file of `timestamp,g_per_kwh` lines. Timestamps are Unix seconds or RFC 3339
+//! (e.g. `2025-03-01T14:00:00Z`); each value holds until the next timestamp, and the
+//! first one also covers the samples before it.
+
+use std::fs;
+use std::path::Path;
+
+use crate::exporter::Record;
+use crate::footprint::{JOULES_PER_KWH, check};
+
+/// Average carbon intensity of electricity generation, in gCO₂e/kWh, by ISO 3166 country
+/// code or `COUNTRY-REGION` code. Rounded yearly averages from Ember's 2023 electricity
+/// data; use a time series file where hourly accuracy matters.
+pub const REGIONS: &[(&str, f64)] = &[
+    ("WORLD", 480.0),
+    ("EU", 244.0),
+    ("AE", 492.0),
+    ("AR", 354.0),
+    ("AT", 110.0),
+    ("AU", 548.0),
+    ("AU-NSW", 660.0),
+    ("AU-QLD", 700.0),
+    ("AU-SA", 200.0),
+    ("AU-TAS", 120.0),
+    ("AU-VIC", 780.0),
+    ("BE", 143.0),
+    ("BR", 98.0),
+    ("CA", 128.0),
+    ("CA-AB", 540.0),
+    ("CA-BC", 20.0),
+    ("CA-ON", 40.0),
+    ("CA-QC", 2.0),
+    ("CH", 35.0),
+    ("CL", 291.0),
+    ("CN", 582.0),
+    ("CZ", 449.0),
+    ("DE", 381.0),
+    ("DK", 151.0),
+    ("EG", 570.0),
+    ("ES", 174.0),
+    ("FI", 79.0),
+    ("FR", 56.0),
+    ("GB", 238.0),
+    ("GR", 336.0),
+    ("HU", 204.0),
+    ("ID", 676.0),
+    ("IE", 282.0),
+    ("IL", 520.0),
+    ("IN", 713.0),
+    ("IS", 28.0),
+    ("IT", 330.0),
+    ("JP", 485.0),
+    ("KE", 79.0),
+    ("KR", 432.0),
+    ("MA", 630.0),
+    ("MX", 423.0),
+    ("MY", 605.0),
+    ("NG", 390.0),
+    ("NL", 268.0),
+    ("NO", 30.0),
+    ("NZ", 112.0),
+    ("PH", 610.0),
+    ("PL", 662.0),
+    ("PT", 165.0),
+    ("RO", 240.0),
+    ("RU", 441.0),
+    ("SA", 706.0),
+    ("SE", 41.0),
+    ("SG", 470.0),
+    ("TH", 550.0),
+    ("TN", 560.0),
+    ("TR", 423.0),
+    ("TW", 561.0),
+    ("UA", 260.0),
+    ("US", 369.0),
+    ("US-AZ", 330.0),
+    ("US-CA", 220.0),
+    ("US-CO", 520.0),
+    ("US-FL", 390.0),
+    ("US-GA", 370.0),
+    ("US-IL", 280.0),
+    ("US-NY", 220.0),
+    ("US-OH", 530.0),
+    ("US-OR", 150.0),
+    ("US-TX", 380.0),
+    ("US-VA", 300.0),
+    ("US-WA", 80.0),
+    ("VN", 475.0),
+    ("ZA", 709.0),
+];
+
+/// Grid carbon intensity over the run.
+#[derive(Clone, Debug)]
+pub enum Intensity {
+    /// The same intensity for every sample, in gCO₂e/kWh.
+    Static(f64),
+    /// Intensity from each timestamp (milliseconds since epoch) on, sorted by time.
+    Series(Vec<(i64, f64)>),
+}
+
+impl Intensity {
+    /// Parses `--carbon-intensity`: gCO₂e/kWh, a region code of [`REGIONS`] or the path
+    /// of a time series file.
+    pub fn parse(spec: &str) -> Result<Intensity, String> {
+        if let Ok(value) = spec.parse::<f64>() {
+            return check("carbon intensity", value).map(Intensity::Static);
+        }
+        let path = Path::new(spec);
+        if path.is_file() {
+            return read_series(path).map_err(|e| format!("{}: {}", path.display(), e));
+        }
+        REGIONS
+            .iter()
+            .find(|(code, _)| code.eq_ignore_ascii_case(spec))
+            .map(|(_, value)| Intensity::Static(*value))
+            .ok_or_else(|| {
+                format!(
+                    "{} is neither gCO2e/kWh, a file nor a known region code (one of {})",
+                    spec,
+                    REGIONS
+                        .iter()
+                        .map(|(code, _)| *code)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+
+    /// Intensity in effect at `timestamp` (milliseconds since epoch), in gCO₂e/kWh.
+    pub fn at(&self, timestamp: i64) -> f64 {
+        match self {
+            Intensity::Static(value) => *value,
+            Intensity::Series(series) => {
+                let next = series.partition_point(|(start, _)| *start <= timestamp);
+                series[next.saturating_sub(1)].1
+            }
+        }
+    }
+
+    /// Sets the emissions of the energy attributed by `record`.
+    pub fn apply(&self, record: &mut Record) {
+        let kwh = record.grid_energy_j() / JOULES_PER_KWH;
+        record.carbon_g = Some(kwh * self.at(record.timestamp));
+    }
+}
+
+/// Reads a `timestamp,g_per_kwh` CSV file, with an optional header line and `#`
+/// comments.
+fn read_series(path: &Path) -> Result<Intensity, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut series = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = line
+            .split_once(',')
+            .ok_or("expected timestamp,g_per_kwh".to_string())
+            .and_then(|(timestamp, value)| {
+                let value = value.trim().parse::<f64>().map_err(|e| e.to_string())?;
+                Ok((
+                    parse_timestamp(timestamp.trim())?,
+                    check("carbon intensity", value)?,
+                ))
+            });
+        match entry {
+            Ok(entry) => series.push(entry),
+            // A header line
+            Err(_) if number == 0 && !line.contains(|c: char| c.is_ascii_digit()) => {}
+            Err(e) => return Err(format!("line {}: {}", number + 1, e)),
+        }
+    }
+    if series.is_empty() {
+        return Err("no intensity values".into());
+    }
+    series.sort_by_key(|(timestamp, _)| *timestamp);
+    Ok(Intensity::Series(series))
+}
+
+/// Parses Unix seconds or an RFC 3339 date-time into milliseconds since epoch.
+fn parse_timestamp(value: &str) -> Result<i64, String> {
+    if let Ok(secs) = value.parse::<f64>() {
+        return Ok((secs * 1000.0) as i64);
+    }
+    let invalid = || {
+        format!(
+            "{} is neither Unix seconds nor an RFC 3339 date-time",
+            value
+        )
+    };
+    let number = |range: std::ops::Range<usize>| -> Result<i64, String> {
+        value
+            .get(range)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(invalid)
+    };
+    let bytes = value.as_bytes();
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    if separators.iter().any(|(i, sep)| bytes.get(*i) != Some(sep))
+        || !matches!(bytes.get(10), Some(b'T' | b't' | b' '))
+    {
+        return Err(invalid());
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    // A leap second is taken as the next second
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(invalid());
+    }
+
+    // Fractional seconds, then the UTC offset
+    let mut rest = &value[19..];
+    let mut millis = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.len()
+            - fraction
+                .trim_start_matches(|c: char| c.is_ascii_digit())
+                .len();
+        let padded = format!("{:0<3}", &fraction[..digits.min(3)]);
+        millis = padded.parse::<i64>().map_err(|_| invalid())?;
+        rest = &fraction[digits..];
+    }
+    let offset_secs = match rest {
+        "Z" | "z" => 0,
+        _ if rest.len() == 6
+            && (rest.starts_with('+') || rest.starts_with('-'))
+            && rest.as_bytes()[3] == b':' =>
+        {
+            let sign = if rest.starts_with('-') { -1 } else { 1 };
+            let part = |range| {
+                rest.get(range)
+                    .filter(|d: &&str| d.bytes().all(|b| b.is_ascii_digit()))
+                    .and_then(|d| d.parse::<i64>().ok())
+            };
+            let (hours, minutes) = part(1..3).zip(part(4..6)).ok_or_else(invalid)?;
+            sign * (hours * 3600 + minutes * 60)
+        }
+        _ => return Err(invalid()),
+    };
+
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+        - offset_secs;
+    Ok(secs * 1000 + millis)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date (Howard Hinnant's algorithm).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-01T00:00:00Z in milliseconds since epoch.
+    const NEW_YEAR: i64 = 1_704_067_200_000;
+
+    #[test]
+    fn parses_unix_seconds_and_rfc_3339() {
+        assert_eq!(parse_timestamp("1704067200"), Ok(NEW_YEAR));
+        assert_eq!(parse_timestamp("1704067200.5"), Ok(NEW_YEAR + 500));
+        assert_eq!(parse_timestamp("2024-01-01T00:00:00Z"), Ok(NEW_YEAR));
+        assert_eq!(parse_timestamp("2024-01-01t00:00:00z"), Ok(NEW_YEAR));
+        assert_eq!(parse_timestamp("2024-01-01 00:00:00Z"), Ok(NEW_YEAR));
+        assert_eq!(parse_timestamp("2024-01-01T01:00:00+01:00"), Ok(NEW_YEAR));
+        assert_eq!(
+            parse_timestamp("2023-12-31T19:30:00.25-04:30"),
+            Ok(NEW_YEAR + 250)
+        );
+        assert_eq!(
+            parse_timestamp("2024-03-01T00:00:00Z"),
+            Ok(1_709_251_200_000)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_timestamps() {
+        for value in [
+            "2024x01y01T00:00:00Z",
+            "2024-01-01T00x00y00Z",
+            "2024-01-01X00:00:00Z",
+            "2024-+1-01T00:00:00Z",
+            "2024-13-01T00:00:00Z",
+            "2024-01-01T24:00:00Z",
+            "2024-01-01T00:00:00",
+            "2024-01-01T00:00:00+0100",
+            "2024-01-01T00:00:00+01x00",
+            "2024-01-01",
+            "yesterday",
+        ] {
+            assert!(parse_timestamp(value).is_err(), "{} was accepted", value);
+        }
+    }
+
+    fn series(text: &str) -> Result<Intensity, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("intensity.csv");
+        fs::write(&path, text).unwrap();
+        read_series(&path)
+    }
+
+    #[test]
+    fn reads_a_sorted_series_with_header_and_comments() {
+        let intensity = series(
+            "timestamp,g_per_kwh\n\
+             # hourly values\n\
+             2024-01-01T01:00:00Z,300\n\
+             \n\
+             2024-01-01T00:00:00Z,200\n",
+        )
+        .unwrap();
+        let Intensity::Series(points) = &intensity else {
+            panic!("not a series");
+        };
+        assert_eq!(points, &[(NEW_YEAR, 200.0), (NEW_YEAR + 3_600_000, 300.0)]);
+
+        // The first value also covers the samples before it, the last one everything after
+        assert_eq!(intensity.at(NEW_YEAR - 1), 200.0);
+        assert_eq!(intensity.at(NEW_YEAR), 200.0);
+        assert_eq!(intensity.at(NEW_YEAR + 3_599_999), 200.0);
+        assert_eq!(intensity.at(NEW_YEAR + 3_600_000), 300.0);
+        assert_eq!(intensity.at(NEW_YEAR + 86_400_000), 300.0);
+    }
+
+    #[test]
+    fn rejects_malformed_rows() {
+        let error = series("1704067200,200\n1704070800;300\n").unwrap_err();
+        assert_eq!(error, "line 2: expected timestamp,g_per_kwh");
+        assert!(
+            series("1704067200,-5\n")
+                .unwrap_err()
+                .starts_with("line 1: ")
+        );
+        assert!(series("2024x01y01T00:00:00Z,200\n").is_err());
+        assert_eq!(
+            series("timestamp,g_per_kwh\n").unwrap_err(),
+            "no intensity values"
+        );
+    }
+
+    #[test]
+    fn looks_up_region_codes() {
+        let Ok(Intensity::Static(de)) = Intensity::parse("de") else {
+            panic!("DE is not a region");
+        };
+        assert_eq!(
+            Some(de),
+            REGIONS.iter().find(|(c, _)| *c == "DE").map(|r| r.1)
+        );
+        assert!(matches!(
+            Intensity::parse("120"),
+            Ok(Intensity::Static(120.0))
+        ));
+        let error = Intensity::parse("XX").unwrap_err();
+        assert!(error.starts_with("XX is neither gCO2e/kWh, a file nor a known region code"));
+        assert!(error.contains("WORLD, EU,"));
+    }
+}
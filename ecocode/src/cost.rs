@@ -0,0 +1,285 @@
+//! Energy cost of the attributed energy.
+//!
+//! The price per kWh is either a flat rate or a time-of-use tariff read from a CSV file
+//! of `days,hours,price` lines, looked up in local time. Days are `mon` to `sun`, a range
+//! such as `mon-fri`, or `*`; hours are an hour (0 to 23), a range such as `8-20` (from
+//! 8:00 up to 20:00; `22-6` wraps around midnight and `0-24` is the whole day), or `*`. Later lines override earlier ones
+//! and every hour of the week must have a price, e.g.:
+//!
+//! ```text
+//! days,hours,price
+//! *,*,0.18
+//! mon-fri,8-20,0.32
+//! ```
+
+use std::fs;
+use std::path::Path;
+
+use crate::exporter::Record;
+use crate::footprint::{JOULES_PER_KWH, check};
+
+const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Price per kWh over the run.
+#[derive(Clone, Debug)]
+pub enum Tariff {
+    Flat(f64),
+    /// Price by day of the week (Sunday first) and hour of the day, in local time.
+    TimeOfUse(Box<[[f64; 24]; 7]>),
+}
+
+impl Tariff {
+    /// Parses `--energy-price`: a price per kWh or the path of a tariff file.
+    pub fn parse(spec: &str) -> Result<Tariff, String> {
+        if let Ok(price) = spec.parse::<f64>() {
+            return check("energy price", price).map(Tariff::Flat);
+        }
+        let path = Path::new(spec);
+        if !path.is_file() {
+            return Err(format!("{} is neither a price nor a tariff file", spec));
+        }
+        read_tariff(path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Price in effect at `timestamp` (milliseconds since epoch), per kWh.
+    pub fn at(&self, timestamp: i64) -> f64 {
+        match self {
+            Tariff::Flat(price) => *price,
+            Tariff::TimeOfUse(prices) => {
+                let (day, hour) = local_day_hour(timestamp);
+                prices[day][hour]
+            }
+        }
+    }
+
+    /// Sets the cost of the energy attributed by `record`.
+    pub fn apply(&self, record: &mut Record) {
+        let kwh = record.grid_energy_j() / JOULES_PER_KWH;
+        record.cost = Some(kwh * self.at(record.timestamp));
+    }
+}
+
+/// Parses `--currency`: a three-letter ISO 4217 code, in any case.
+pub fn parse_currency(code: &str) -> Result<String, String> {
+    if code.len() == 3 && code.bytes().all(|b| b.is_ascii_alphabetic()) {
+        Ok(code.to_ascii_uppercase())
+    } else {
+        Err(format!(
+            "{} is not a three-letter currency code (e.g. EUR)",
+            code
+        ))
+    }
+}
+
+/// Reads a `days,hours,price` CSV file, with an optional header line and `#` comments.
+fn read_tariff(path: &Path) -> Result<Tariff, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut prices = [[None; 24]; 7];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_entry(line) {
+            Ok((days, hours, price)) => {
+                for day in &days {
+                    for hour in &hours {
+                        prices[*day][*hour] = Some(price);
+                    }
+                }
+            }
+            // A header line
+            Err(_) if number == 0 && !line.contains(|c: char| c.is_ascii_digit()) => {}
+            Err(e) => return Err(format!("line {}: {}", number + 1, e)),
+        }
+    }
+
+    let mut tariff = [[0.0; 24]; 7];
+    for (day, hours) in prices.iter().enumerate() {
+        for (hour, price) in hours.iter().enumerate() {
+            tariff[day][hour] =
+                price.ok_or_else(|| format!("no price for {} {}:00", DAYS[day], hour))?;
+        }
+    }
+    Ok(Tariff::TimeOfUse(Box::new(tariff)))
+}
+
+/// Parses a `days,hours,price` line into the days, hours and price it sets.
+fn parse_entry(line: &str) -> Result<(Vec<usize>, Vec<usize>, f64), String> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    let [days, hours, price] = fields[..] else {
+        return Err("expected days,hours,price".into());
+    };
+    let price = check(
+        "energy price",
+        price
+            .parse::<f64>()
+            .map_err(|e| format!("{}: {}", price, e))?,
+    )?;
+
+    let day = |name: &str| {
+        DAYS.iter()
+            .position(|d| d.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown day {}, expected mon to sun", name))
+    };
+    let days = match days.split_once('-') {
+        _ if days == "*" => (0..7).collect(),
+        Some((first, last)) => wrapping_range(day(first)?, day(last)?, 7, true),
+        None => vec![day(days)?],
+    };
+
+    // 24 is only valid as the (exclusive) end of a range
+    let hour = |value: &str, max: usize| match value.parse::<usize>() {
+        Ok(hour) if hour <= max => Ok(hour),
+        _ => Err(format!("invalid hour {}, expected 0 to {}", value, max)),
+    };
+    let hours = match hours.split_once('-') {
+        _ if hours == "*" => (0..24).collect(),
+        Some((start, end)) => {
+            let (start, end) = (hour(start, 23)?, hour(end, 24)?);
+            if start == end {
+                return Err(format!("empty hour range {}-{}", start, end));
+            }
+            wrapping_range(start, end % 24, 24, false)
+        }
+        None => vec![hour(hours, 23)?],
+    };
+    Ok((days, hours, price))
+}
+
+/// The values from `first` to `last` modulo `len`, wrapping around; `last` is left out
+/// unless `inclusive`. An exclusive range that ends where it starts, i.e. hours `0-24`,
+/// covers all values.
+fn wrapping_range(first: usize, last: usize, len: usize, inclusive: bool) -> Vec<usize> {
+    let end = if inclusive { (last + 1) % len } else { last };
+    let mut values = vec![first];
+    let mut value = (first + 1) % len;
+    while value != end {
+        values.push(value);
+        value = (value + 1) % len;
+    }
+    values
+}
+
+/// Day of the week (0 is Sunday) and hour of `timestamp` in local time.
+fn local_day_hour(timestamp: i64) -> (usize, usize) {
+    let secs = timestamp.div_euclid(1000) as libc::time_t;
+    // SAFETY: localtime_r only writes to the given tm.
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&secs, &mut tm).is_null() {
+            return (0, 0);
+        }
+        tm
+    };
+    (tm.tm_wday as usize % 7, tm.tm_hour as usize % 24)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUN: usize = 0;
+    const MON: usize = 1;
+    const FRI: usize = 5;
+    const SAT: usize = 6;
+
+    fn tariff(text: &str) -> Result<Box<[[f64; 24]; 7]>, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tariff.csv");
+        fs::write(&path, text).unwrap();
+        match Tariff::parse(path.to_str().unwrap()) {
+            Ok(Tariff::TimeOfUse(prices)) => Ok(prices),
+            Ok(Tariff::Flat(_)) => panic!("a tariff file parsed as a flat rate"),
+            Err(e) => Err(e
+                .strip_prefix(&format!("{}: ", path.display()))
+                .unwrap()
+                .into()),
+        }
+    }
+
+    #[test]
+    fn flat_rate_prices_every_sample() {
+        let tariff = Tariff::parse("0.25").unwrap();
+        assert_eq!(tariff.at(0), 0.25);
+        assert_eq!(tariff.at(1_704_067_200_000), 0.25);
+        assert!(matches!(Tariff::parse("0"), Ok(Tariff::Flat(0.0))));
+    }
+
+    #[test]
+    fn later_entries_override_earlier_ones() {
+        let prices = tariff(
+            "days,hours,price\n\
+             # base rate, then peak hours on weekdays\n\
+             *,*,0.18\n\
+             mon-fri,8-20,0.32\n\
+             fri,19,0.40\n",
+        )
+        .unwrap();
+        assert_eq!(prices[MON][7], 0.18);
+        assert_eq!(prices[MON][8], 0.32);
+        assert_eq!(prices[MON][19], 0.32);
+        assert_eq!(prices[MON][20], 0.18);
+        assert_eq!(prices[FRI][19], 0.40);
+        assert_eq!(prices[SAT][12], 0.18);
+    }
+
+    #[test]
+    fn ranges_wrap_around_midnight_and_the_week() {
+        let prices = tariff(
+            "*,0-24,0.30\n\
+             fri-mon,22-6,0.10\n",
+        )
+        .unwrap();
+        for day in [FRI, SAT, SUN, MON] {
+            assert_eq!(prices[day][22], 0.10);
+            assert_eq!(prices[day][23], 0.10);
+            assert_eq!(prices[day][0], 0.10);
+            assert_eq!(prices[day][5], 0.10);
+            assert_eq!(prices[day][6], 0.30);
+            assert_eq!(prices[day][21], 0.30);
+        }
+        assert_eq!(prices[2][23], 0.30);
+    }
+
+    #[test]
+    fn rejects_bad_entries() {
+        for (line, error) in [
+            ("*,24,0.30", "line 2: invalid hour 24, expected 0 to 23"),
+            ("*,8-8,0.30", "line 2: empty hour range 8-8"),
+            ("*,8-25,0.30", "line 2: invalid hour 25, expected 0 to 24"),
+            ("*,24-8,0.30", "line 2: invalid hour 24, expected 0 to 23"),
+            (
+                "mon-fry,*,0.30",
+                "line 2: unknown day fry, expected mon to sun",
+            ),
+            ("*,*,-0.30", "line 2: energy price -0.3 is negative"),
+            ("*,*", "line 2: expected days,hours,price"),
+            ("*,*,cheap", "line 2: cheap: invalid float literal"),
+        ] {
+            let text = format!("*,*,0.30\n{}\n", line);
+            assert_eq!(tariff(&text).unwrap_err(), error);
+        }
+        // A first line without digits is a header
+        assert!(tariff("days,hours,price\n*,*,0.30\n").is_ok());
+        assert_eq!(
+            tariff("mon-sun,*,0.30\nsat,*,\n").unwrap_err(),
+            "line 2: : cannot parse float from empty string"
+        );
+        assert_eq!(
+            tariff("mon-fri,*,0.30\n").unwrap_err(),
+            "no price for sun 0:00"
+        );
+        assert!(Tariff::parse("-1").is_err());
+        assert!(Tariff::parse("no-such-file.csv").is_err());
+    }
+
+    #[test]
+    fn currency_is_a_three_letter_code() {
+        assert_eq!(parse_currency("eur"), Ok("EUR".to_string()));
+        assert_eq!(parse_currency("USD"), Ok("USD".to_string()));
+        for code in ["", "EU", "EURO", "E1R", "€"] {
+            assert!(parse_currency(code).is_err(), "{} was accepted", code);
+        }
+    }
+}
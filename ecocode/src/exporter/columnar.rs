@@ -0,0 +1,388 @@
+//! Columnar exporter — typed Parquet or Arrow IPC files for analysis with pandas/polars.
+//!
+//! Records are buffered and written in record batches. The run metadata (command,
+//! host, hardware, labels, ...) is stored as key/value metadata of the schema and of
+//! the Parquet file, and the end-of-run summary is added to the file-level metadata
+//! when the file is finished.
+//! Keys are prefixed with `ecocode.`; structured values are JSON.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::sync::Arc;
+
+use arrow_array::builder::{
+    BooleanBuilder, Float64Builder, StringBuilder, TimestampMillisecondBuilder, UInt32Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+
+use crate::exporter::{Exporter, ExporterType, Record, RunInfo};
+use crate::summary::Summary;
+
+/// Records per record batch, each written out as its own Parquet row group.
+const BATCH_ROWS: usize = 4096;
+
+/// Prefix of every metadata key written by EcoCode.
+const METADATA_PREFIX: &str = "ecocode.";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Parquet,
+    ArrowIpc,
+}
+
+enum Writer {
+    Parquet(ArrowWriter<File>),
+    ArrowIpc(FileWriter<File>),
+}
+
+/// Writes records to a Parquet or Arrow IPC file.
+pub struct ColumnarExporter {
+    file_path: String,
+    format: Format,
+    run: RunInfo,
+    /// Created with the first record, whose CPU domains fix the schema, or at the end of
+    /// a run without records.
+    writer: Option<(Writer, SchemaRef)>,
+    pending: Vec<Record>,
+}
+
+impl ColumnarExporter {
+    pub fn new(
+        file_path: String,
+        format: Format,
+        run: RunInfo,
+    ) -> Result<ColumnarExporter, Box<dyn Error>> {
+        // Fail at startup rather than at the first record if the path is not writable
+        File::create(&file_path)?;
+        Ok(ColumnarExporter {
+            file_path,
+            format,
+            run,
+            writer: None,
+            pending: Vec::with_capacity(BATCH_ROWS),
+        })
+    }
+
+    fn open(&self, first: Option<&Record>) -> Result<(Writer, SchemaRef), Box<dyn Error>> {
+        let metadata = self.run_metadata()?;
+        let schema = Arc::new(schema(first).with_metadata(metadata.clone()));
+        let file = File::create(&self.file_path)?;
+        let writer = match self.format {
+            Format::Parquet => {
+                // Also as plain key/value metadata, for readers that ignore the embedded
+                // Arrow schema
+                let props = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .set_key_value_metadata(Some(
+                        metadata
+                            .into_iter()
+                            .map(|(key, value)| KeyValue::new(key, value))
+                            .collect(),
+                    ))
+                    .build();
+                Writer::Parquet(ArrowWriter::try_new(file, schema.clone(), Some(props))?)
+            }
+            Format::ArrowIpc => Writer::ArrowIpc(FileWriter::try_new(file, &schema)?),
+        };
+        Ok((writer, schema))
+    }
+
+    fn run_metadata(&self) -> Result<HashMap<String, String>, Box<dyn Error>> {
+        let run = &self.run;
+        Ok([
+            ("run_id", run.id.clone()),
+            ("command", run.command.clone()),
+            ("args", serde_json::to_string(&run.args)?),
+            ("started_at", run.started_at.to_string()),
+            ("host", run.host.clone()),
+            ("hardware", serde_json::to_string(&run.hardware)?),
+            ("labels", serde_json::to_string(&run.labels)?),
+            ("currency", run.currency.clone().unwrap_or_default()),
+        ]
+        .into_iter()
+        .map(|(key, value)| (format!("{}{}", METADATA_PREFIX, key), value))
+        .collect())
+    }
+
+    /// Writes the pending records as one record batch, which becomes a Parquet row group
+    /// of its own.
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(first) = self.pending.first() else {
+            return Ok(());
+        };
+        if self.writer.is_none() {
+            self.writer = Some(self.open(Some(first))?);
+        }
+        let (writer, schema) = self.writer.as_mut().unwrap();
+        let batch = record_batch(schema.clone(), &self.pending)?;
+        match writer {
+            Writer::Parquet(w) => {
+                w.write(&batch)?;
+                // Otherwise the row group stays buffered up to max_row_group_size rows
+                w.flush()?;
+            }
+            Writer::ArrowIpc(w) => {
+                w.write(&batch)?;
+                w.flush()?;
+            }
+        }
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// Schema of the records; one column per CPU domain of `first`, if any.
+fn schema(first: Option<&Record>) -> Schema {
+    let mut fields = vec![
+        Field::new("id", DataType::UInt32, false),
+        Field::new("pid", DataType::UInt32, false),
+        Field::new("scope", DataType::Utf8, false),
+        Field::new("ppid", DataType::UInt32, true),
+        Field::new("name", DataType::Utf8, true),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("interval_secs", DataType::Float64, false),
+        Field::new("cpu_usage", DataType::Float64, false),
+        Field::new("cpu_power_w", DataType::Float64, false),
+    ];
+    fields.extend(
+        first
+            .into_iter()
+            .flat_map(|first| first.cpu_domains.keys())
+            .map(|domain| Field::new(format!("cpu_domains.{}", domain), DataType::Float64, true)),
+    );
+    fields.extend([
+        Field::new("gpu_usage", DataType::Float64, true),
+        Field::new("gpu_power_w", DataType::Float64, true),
+        Field::new("system_power_w", DataType::Float64, true),
+        Field::new("wall_power_w", DataType::Float64, true),
+        Field::new("facility_power_w", DataType::Float64, true),
+        Field::new("carbon_g", DataType::Float64, true),
+        Field::new("cost", DataType::Float64, true),
+        Field::new("water_l", DataType::Float64, true),
+        Field::new("counter_wrapped", DataType::Boolean, false),
+    ]);
+    Schema::new(fields)
+}
+
+fn record_batch(schema: SchemaRef, records: &[Record]) -> Result<RecordBatch, Box<dyn Error>> {
+    let mut id = UInt32Builder::with_capacity(records.len());
+    let mut pid = UInt32Builder::with_capacity(records.len());
+    let mut scope = StringBuilder::new();
+    let mut ppid = UInt32Builder::with_capacity(records.len());
+    let mut name = StringBuilder::new();
+    let mut timestamp = TimestampMillisecondBuilder::with_capacity(records.len());
+    let mut interval = Float64Builder::with_capacity(records.len());
+    let mut cpu_usage = Float64Builder::with_capacity(records.len());
+    let mut cpu_power_w = Float64Builder::with_capacity(records.len());
+    let domains: Vec<&str> = schema
+        .fields()
+        .iter()
+        .filter_map(|f| f.name().strip_prefix("cpu_domains."))
+        .collect();
+    let mut domain_columns: Vec<Float64Builder> = domains
+        .iter()
+        .map(|_| Float64Builder::with_capacity(records.len()))
+        .collect();
+    let mut gpu_usage = Float64Builder::with_capacity(records.len());
+    let mut gpu_power_w = Float64Builder::with_capacity(records.len());
+    let mut system_power_w = Float64Builder::with_capacity(records.len());
+    let mut wall_power_w = Float64Builder::with_capacity(records.len());
+    let mut facility_power_w = Float64Builder::with_capacity(records.len());
+    let mut carbon = Float64Builder::with_capacity(records.len());
+    let mut cost = Float64Builder::with_capacity(records.len());
+    let mut water = Float64Builder::with_capacity(records.len());
+    let mut wrapped = BooleanBuilder::with_capacity(records.len());
+
+    for r in records {
+        id.append_value(r.id);
+        pid.append_value(r.pid);
+        scope.append_value(r.scope.as_str());
+        ppid.append_option(r.ppid);
+        name.append_option(r.name.as_deref());
+        timestamp.append_value(r.timestamp);
+        interval.append_value(r.interval_secs);
+        cpu_usage.append_value(r.cpu_usage);
+        cpu_power_w.append_value(r.cpu_power_w);
+        for (column, domain) in domain_columns.iter_mut().zip(&domains) {
+            column.append_option(r.cpu_domains.get(*domain).copied());
+        }
+        gpu_usage.append_option(r.gpu_usage);
+        gpu_power_w.append_option(r.gpu_power_w);
+        system_power_w.append_option(r.system_power_w);
+        wall_power_w.append_option(r.wall_power_w);
+        facility_power_w.append_option(r.facility_power_w);
+        carbon.append_option(r.carbon_g);
+        cost.append_option(r.cost);
+        water.append_option(r.water_l);
+        wrapped.append_value(r.counter_wrapped);
+    }
+
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(id.finish()),
+        Arc::new(pid.finish()),
+        Arc::new(scope.finish()),
+        Arc::new(ppid.finish()),
+        Arc::new(name.finish()),
+        Arc::new(timestamp.finish().with_timezone("UTC")),
+        Arc::new(interval.finish()),
+        Arc::new(cpu_usage.finish()),
+        Arc::new(cpu_power_w.finish()),
+    ];
+    columns.extend(
+        domain_columns
+            .iter_mut()
+            .map(|c| Arc::new(c.finish()) as ArrayRef),
+    );
+    columns.extend([
+        Arc::new(gpu_usage.finish()) as ArrayRef,
+        Arc::new(gpu_power_w.finish()),
+        Arc::new(system_power_w.finish()),
+        Arc::new(wall_power_w.finish()),
+        Arc::new(facility_power_w.finish()),
+        Arc::new(carbon.finish()),
+        Arc::new(cost.finish()),
+        Arc::new(water.finish()),
+        Arc::new(wrapped.finish()),
+    ]);
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+impl Exporter for ColumnarExporter {
+    fn exporter_type(&self) -> ExporterType {
+        match self.format {
+            Format::Parquet => ExporterType::Parquet,
+            Format::ArrowIpc => ExporterType::ArrowIpc,
+        }
+    }
+
+    fn add_record(&mut self, record: Record) -> Result<(), Box<dyn Error>> {
+        self.pending.push(record);
+        if self.pending.len() >= BATCH_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn export(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        self.flush()?;
+        // Without any record, the file still gets the schema and the run metadata
+        if self.writer.is_none() {
+            self.writer = Some(self.open(None)?);
+        }
+        let summary_key = format!("{}summary", METADATA_PREFIX);
+        let summary = serde_json::to_string(summary)?;
+        match &mut self.writer {
+            Some((Writer::Parquet(w), _)) => {
+                w.append_key_value_metadata(KeyValue::new(summary_key, summary));
+                w.finish()?;
+            }
+            Some((Writer::ArrowIpc(w), _)) => {
+                w.write_metadata(summary_key, summary);
+                w.finish()?;
+            }
+            None => unreachable!("the writer is opened above"),
+        }
+
+        match self.format {
+            Format::Parquet => eprintln!("\n[PARQUET EXPORT]"),
+            Format::ArrowIpc => eprintln!("\n[ARROW IPC EXPORT]"),
+        }
+        eprintln!("Records found in File: {}", self.file_path);
+        Ok(())
+    }
+
+    fn export_line(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::testing;
+    use crate::summary::EnergyMeter;
+    use arrow_ipc::reader::FileReader as IpcReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    #[test]
+    fn writes_a_row_group_per_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.parquet");
+        let mut exporter = ColumnarExporter::new(
+            path.to_string_lossy().into_owned(),
+            Format::Parquet,
+            testing::run(),
+        )
+        .unwrap();
+        let mut meter = EnergyMeter::new();
+        for id in 1..=BATCH_ROWS as u32 + 1 {
+            let record = testing::record(id);
+            meter.add_record(&record);
+            exporter.add_record(record).unwrap();
+        }
+        exporter.export(&meter.summary(42, 0)).unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(metadata.num_row_groups(), 2);
+        assert_eq!(metadata.row_group(0).num_rows(), BATCH_ROWS as i64);
+        assert_eq!(metadata.row_group(1).num_rows(), 1);
+    }
+
+    #[test]
+    fn an_empty_run_still_has_the_schema_and_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        for format in [Format::Parquet, Format::ArrowIpc] {
+            let path = dir.path().join(format!("{:?}", format));
+            let mut exporter =
+                ColumnarExporter::new(path.to_string_lossy().into_owned(), format, testing::run())
+                    .unwrap();
+            exporter.export(&EnergyMeter::new().summary(42, 0)).unwrap();
+
+            let (schema, rows, metadata) = match format {
+                Format::Parquet => {
+                    let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+                    let rows = reader.metadata().file_metadata().num_rows() as usize;
+                    let metadata: HashMap<String, String> = reader
+                        .metadata()
+                        .file_metadata()
+                        .key_value_metadata()
+                        .unwrap()
+                        .iter()
+                        .filter_map(|kv| Some((kv.key.clone(), kv.value.clone()?)))
+                        .collect();
+                    // Also as plain key/value metadata of the file
+                    assert_eq!(metadata["ecocode.run_id"], "1700000000000-42");
+                    let file = File::open(&path).unwrap();
+                    let builder = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+                    (builder.schema().clone(), rows, metadata)
+                }
+                Format::ArrowIpc => {
+                    let reader = IpcReader::try_new(File::open(&path).unwrap(), None).unwrap();
+                    let metadata = reader.custom_metadata().clone();
+                    let schema = reader.schema();
+                    let rows = reader.map(|batch| batch.unwrap().num_rows()).sum();
+                    (schema, rows, metadata)
+                }
+            };
+            assert_eq!(rows, 0, "{:?}", format);
+            assert!(schema.field_with_name("cpu_power_w").is_ok());
+            assert_eq!(schema.metadata()["ecocode.run_id"], "1700000000000-42");
+            let summary: serde_json::Value =
+                serde_json::from_str(&metadata["ecocode.summary"]).unwrap();
+            assert_eq!(summary["exit_code"], 0);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! Composite exporter — fans every record out to several exporters.
+
+use std::error::Error;
+
+use crate::exporter::{Exporter, ExporterType, Record};
+use crate::summary::Summary;
+
+/// Forwards every call to each of its sinks.
+///
+/// A failing sink does not stop the others: its error is reported on stderr, naming the
+/// sink, and the call only fails when every sink failed.
+pub struct CompositeExporter {
+    sinks: Vec<Box<dyn Exporter>>,
+}
+
+impl CompositeExporter {
+    pub fn new(sinks: Vec<Box<dyn Exporter>>) -> CompositeExporter {
+        CompositeExporter { sinks }
+    }
+
+    fn for_each(
+        &mut self,
+        action: &str,
+        mut call: impl FnMut(&mut dyn Exporter) -> Result<(), Box<dyn Error>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut failures = Vec::new();
+        for sink in &mut self.sinks {
+            if let Err(e) = call(sink.as_mut()) {
+                eprintln!(
+                    "{:?} exporter failed to {}: {}",
+                    sink.exporter_type(),
+                    action,
+                    e
+                );
+                failures.push(format!("{:?}: {}", sink.exporter_type(), e));
+            }
+        }
+        if !self.sinks.is_empty() && failures.len() == self.sinks.len() {
+            return Err(format!(
+                "every exporter failed to {}: {}",
+                action,
+                failures.join("; ")
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl Exporter for CompositeExporter {
+    fn exporter_type(&self) -> ExporterType {
+        ExporterType::Composite
+    }
+
+    fn add_record(&mut self, record: Record) -> Result<(), Box<dyn Error>> {
+        self.for_each("add a record", |sink| sink.add_record(record.clone()))
+    }
+
+    fn export(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        self.for_each("export", |sink| sink.export(summary))
+    }
+
+    fn export_line(&mut self) -> Result<(), Box<dyn Error>> {
+        self.for_each("export a line", |sink| sink.export_line())
+    }
+
+    fn end_interval(&mut self) -> Result<(), Box<dyn Error>> {
+        self.for_each("end the interval", |sink| sink.end_interval())
+    }
+}
@@ -1,6 +1,7 @@
 use std::fs::File;
 
-use crate::exporter::{Exporter, ExporterType, Record};
+use crate::exporter::{Exporter, ExporterType, Record, summary_path};
+use crate::summary::Summary;
 use csv::Writer;
 
 pub struct CsvExporter {
@@ -27,27 +28,27 @@ impl Exporter for CsvExporter {
     }
     fn add_record(&mut self, record: Record) -> Result<(), Box<dyn std::error::Error>> {
         if self.first_record {
-            self.writer
-                .write_record(vec![
-                    "ID",
-                    "PID",
-                    "Timestamp",
-                    "CPU%",
-                    "CPU(W)",
-                    "GPU%",
-                    "GPU(W)",
-                ])
-                .unwrap();
+            self.writer.write_record(record.headers())?;
         }
         self.first_record = false;
-        self.writer.write_record(&record.to_vec()).unwrap();
-        self.writer.flush().unwrap();
+        self.writer.write_record(record.to_vec())?;
+        self.writer.flush()?;
         Ok(())
     }
 
-    fn export(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        println!("\n[CSV EXPORT]");
-        println!("Records found in  File: {}", self.file_path);
+    fn export(&mut self, summary: &Summary) -> Result<(), Box<dyn std::error::Error>> {
+        // The summary has different columns, so it goes to its own file
+        let path = summary_path(&self.file_path);
+        let mut writer = csv::Writer::from_path(&path)?;
+        writer.write_record(["Metric", "Value"])?;
+        for (metric, value) in summary.to_rows() {
+            writer.write_record([metric, value])?;
+        }
+        writer.flush()?;
+
+        eprintln!("\n[CSV EXPORT]");
+        eprintln!("Records found in File: {}", self.file_path);
+        eprintln!("Summary found in File: {}", path.display());
         Ok(())
     }
     fn export_line(&mut self) -> Result<(), Box<dyn std::error::Error>> {
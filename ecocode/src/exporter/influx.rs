@@ -0,0 +1,498 @@
+//! InfluxDB exporter — writes records in the InfluxDB line protocol.
+//!
+//! Every record becomes one `ecocode` point tagged with the host, run ID, PID, scope and
+//! command, with power (W), utilisation (%) and the energy attributed since the start of
+//! the run (J) as fields, per measured component. The `wall` and `facility` power and
+//! energy estimated with the PSU and PUE options are `estimated_`-prefixed fields, as
+//! they include the measured components. The emissions (`carbon_g`, gCO₂e), cost (`cost`,
+//! in the currency of the `currency` tag) and water (`water_l`, L) since the start are
+//! fields too with `--carbon-intensity`, `--energy-price` and the water options. Points
+//! have a nanosecond timestamp. The run summary is a final `ecocode_run` point.
+//!
+//! The destination is a file, stdout (`-`), a UDP listener (`udp://HOST:PORT`, e.g. a
+//! Telegraf `socket_listener`) or an HTTP write endpoint (`http://...` or `https://...`,
+//! e.g. `http://localhost:8086/api/v2/write?org=lab&bucket=ecocode`). HTTP points are
+//! sent once per sampling interval, with the token from `INFLUX_TOKEN` if it is set.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::UdpSocket;
+use std::time::{Duration, SystemTime};
+
+use ureq::Agent;
+
+use crate::exporter::{Exporter, ExporterType, Record, RunInfo, STDOUT, record_stdout};
+use crate::summary::Summary;
+
+/// Prefix of a UDP destination.
+pub const UDP_PREFIX: &str = "udp://";
+
+/// Environment variable holding the API token sent to an HTTP endpoint.
+pub const TOKEN_VAR: &str = "INFLUX_TOKEN";
+
+/// Measurement of the per-record points.
+const MEASUREMENT: &str = "ecocode";
+
+/// Measurement of the end-of-run summary point.
+const SUMMARY_MEASUREMENT: &str = "ecocode_run";
+
+/// Tags set by EcoCode, which `--label` keys cannot replace.
+const RESERVED_TAGS: &[&str] = &["command", "currency", "host", "pid", "run", "scope"];
+
+/// Longest an HTTP write may take before it is given up.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Lines kept for an unreachable HTTP endpoint; older ones are dropped beyond this.
+const MAX_PENDING_LINES: usize = 100_000;
+
+enum Sink {
+    Stream(BufWriter<File>),
+    Udp(UdpSocket),
+    Http {
+        agent: Agent,
+        url: String,
+        token: Option<String>,
+        /// Lines not yet accepted by the endpoint.
+        pending: Vec<String>,
+    },
+}
+
+/// Writes records as InfluxDB line protocol.
+pub struct InfluxExporter {
+    destination: String,
+    sink: Sink,
+    run: RunInfo,
+    /// Energy attributed so far per (scope, PID) series and component, in joules.
+    energy_j: BTreeMap<(&'static str, u32), BTreeMap<&'static str, f64>>,
+    /// Emissions so far per (scope, PID) series, in gCO₂e.
+    carbon_g: BTreeMap<(&'static str, u32), f64>,
+    /// Cost so far per (scope, PID) series.
+    cost: BTreeMap<(&'static str, u32), f64>,
+    /// Water so far per (scope, PID) series, in litres.
+    water_l: BTreeMap<(&'static str, u32), f64>,
+}
+
+impl InfluxExporter {
+    pub fn new(destination: String, run: RunInfo) -> Result<InfluxExporter, Box<dyn Error>> {
+        let sink = if destination == STDOUT {
+            Sink::Stream(BufWriter::new(record_stdout()?))
+        } else if let Some(addr) = destination.strip_prefix(UDP_PREFIX) {
+            let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+            socket.connect(addr)?;
+            Sink::Udp(socket)
+        } else if destination.starts_with("http://") || destination.starts_with("https://") {
+            let agent = Agent::config_builder()
+                .timeout_global(Some(HTTP_TIMEOUT))
+                .build()
+                .into();
+            Sink::Http {
+                agent,
+                url: destination.clone(),
+                token: std::env::var(TOKEN_VAR).ok(),
+                pending: Vec::new(),
+            }
+        } else {
+            Sink::Stream(BufWriter::new(File::create(&destination)?))
+        };
+        Ok(InfluxExporter {
+            destination,
+            sink,
+            run,
+            energy_j: BTreeMap::new(),
+            carbon_g: BTreeMap::new(),
+            cost: BTreeMap::new(),
+            water_l: BTreeMap::new(),
+        })
+    }
+
+    /// The record as one line, without the trailing newline.
+    fn record_line(&mut self, record: &Record) -> String {
+        let mut line = String::from(MEASUREMENT);
+        self.write_run_tags(&mut line);
+        let _ = write!(line, ",pid={},scope={}", record.pid, record.scope.as_str());
+
+        let mut fields = Fields::default();
+        fields.float("cpu_usage", record.cpu_usage);
+        if let Some(usage) = record.gpu_usage {
+            fields.float("gpu_usage", usage);
+        }
+        let energy_j = self
+            .energy_j
+            .entry((record.scope.as_str(), record.pid))
+            .or_default();
+        let components = [
+            ("cpu", Some(record.cpu_power_w)),
+            ("gpu", record.gpu_power_w),
+            ("system", record.system_power_w),
+            ("estimated_wall", record.wall_power_w),
+            ("estimated_facility", record.facility_power_w),
+        ];
+        for (component, power_w) in components {
+            if let Some(power_w) = power_w {
+                let total = energy_j.entry(component).or_default();
+                *total += record.joules(power_w);
+                fields.float(&format!("{}_power_w", component), power_w);
+                fields.float(&format!("{}_joules", component), *total);
+            }
+        }
+        if let Some(grams) = record.carbon_g {
+            let total = self
+                .carbon_g
+                .entry((record.scope.as_str(), record.pid))
+                .or_default();
+            *total += grams;
+            fields.float("carbon_g", *total);
+        }
+        if let Some(cost) = record.cost {
+            let total = self
+                .cost
+                .entry((record.scope.as_str(), record.pid))
+                .or_default();
+            *total += cost;
+            fields.float("cost", *total);
+        }
+        if let Some(litres) = record.water_l {
+            let total = self
+                .water_l
+                .entry((record.scope.as_str(), record.pid))
+                .or_default();
+            *total += litres;
+            fields.float("water_l", *total);
+        }
+        fields.bool("counter_wrapped", record.counter_wrapped);
+
+        let _ = write!(line, " {} {}", fields.0, record.timestamp * 1_000_000);
+        line
+    }
+
+    /// The run summary as one line, timestamped now.
+    fn summary_line(&self, summary: &Summary) -> Result<String, Box<dyn Error>> {
+        let mut line = String::from(SUMMARY_MEASUREMENT);
+        self.write_run_tags(&mut line);
+        let mut fields = Fields::default();
+        if let Some(exit_code) = summary.exit_code {
+            fields.int("exit_code", exit_code.into());
+        }
+        if let Some(error) = &summary.error {
+            fields.string("error", error);
+        }
+        fields.float("duration_s", summary.duration_secs);
+        fields.int("samples", summary.samples as i64);
+        fields.float("cpu_joules", summary.cpu_energy.joules);
+        if let Some(energy) = summary.gpu_energy {
+            fields.float("gpu_joules", energy.joules);
+        }
+        if let Some(energy) = summary.system_energy {
+            fields.float("system_joules", energy.joules);
+        }
+        fields.float("total_joules", summary.total_energy.joules);
+        if let Some(energy) = summary.wall_energy {
+            fields.float("estimated_wall_joules", energy.joules);
+        }
+        if let Some(energy) = summary.facility_energy {
+            fields.float("estimated_facility_joules", energy.joules);
+        }
+        fields.float("mean_power_w", summary.mean_power_w);
+        fields.float("peak_power_w", summary.peak_power_w);
+        fields.float("p95_power_w", summary.p95_power_w);
+        if let Some(carbon) = summary.carbon {
+            fields.float("carbon_g", carbon.grams);
+            fields.float("carbon_intensity_g_per_kwh", carbon.intensity_g_per_kwh);
+        }
+        if let Some(cost) = &summary.cost {
+            fields.float("cost", cost.amount);
+            fields.float("cost_price_per_kwh", cost.price_per_kwh);
+        }
+        if let Some(water) = summary.water {
+            fields.float("water_l", water.litres);
+            fields.float("water_l_per_kwh", water.l_per_kwh);
+        }
+
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+        let _ = write!(line, " {} {}", fields.0, now.as_nanos());
+        Ok(line)
+    }
+
+    /// Appends the tags shared by every point of the run: the run's identity, then its
+    /// labels.
+    fn write_run_tags(&self, line: &mut String) {
+        let tags = [
+            ("command", self.run.command.as_str()),
+            ("currency", self.run.currency.as_deref().unwrap_or_default()),
+            ("host", self.run.host.as_str()),
+            ("run", self.run.id.as_str()),
+        ];
+        // A label cannot override a tag EcoCode sets itself
+        let labels = self
+            .run
+            .labels
+            .iter()
+            .filter(|(key, _)| !RESERVED_TAGS.contains(&key.as_str()))
+            .map(|(key, value)| (key.as_str(), value.as_str()));
+        for (key, value) in tags.into_iter().chain(labels) {
+            // Empty tag values are invalid in line protocol
+            if !value.is_empty() {
+                let _ = write!(line, ",{}={}", escape_tag(key), escape_tag(value));
+            }
+        }
+    }
+
+    /// Writes the line to a file, stdout or UDP right away; queues it for HTTP.
+    fn write_line(&mut self, line: String) -> Result<(), Box<dyn Error>> {
+        match &mut self.sink {
+            Sink::Stream(writer) => {
+                writer.write_all(line.as_bytes())?;
+                writer.write_all(b"\n")?;
+                writer.flush()?;
+            }
+            Sink::Udp(socket) => {
+                // Datagrams are fire-and-forget: a listener that is down (ECONNREFUSED)
+                // loses the line but must not end the measurement
+                if let Err(e) = socket.send(line.as_bytes()) {
+                    eprintln!("InfluxDB send to {} failed: {}", self.destination, e);
+                }
+            }
+            Sink::Http { pending, .. } => {
+                pending.push(line);
+                if pending.len() > MAX_PENDING_LINES {
+                    pending.drain(..pending.len() - MAX_PENDING_LINES);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the queued lines to the HTTP endpoint. On failure they stay queued and are
+    /// retried with the next flush.
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        let Sink::Http {
+            agent,
+            url,
+            token,
+            pending,
+        } = &mut self.sink
+        else {
+            return Ok(());
+        };
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let mut body = pending.join("\n");
+        body.push('\n');
+        let mut request = agent
+            .post(url.as_str())
+            .content_type("text/plain; charset=utf-8");
+        if let Some(token) = token {
+            request = request.header("Authorization", format!("Token {}", token));
+        }
+        request.send(body)?;
+        pending.clear();
+        Ok(())
+    }
+}
+
+/// Field set of a line, in insertion order.
+#[derive(Default)]
+struct Fields(String);
+
+impl Fields {
+    fn push(&mut self, key: &str, value: impl std::fmt::Display) {
+        if !self.0.is_empty() {
+            self.0.push(',');
+        }
+        let _ = write!(self.0, "{}={}", escape_tag(key), value);
+    }
+
+    /// NaN and infinities cannot be written in line protocol and are left out.
+    fn float(&mut self, key: &str, value: f64) {
+        if value.is_finite() {
+            self.push(key, value);
+        }
+    }
+
+    fn int(&mut self, key: &str, value: i64) {
+        self.push(key, format_args!("{}i", value));
+    }
+
+    fn bool(&mut self, key: &str, value: bool) {
+        self.push(key, value);
+    }
+
+    /// Double quotes and backslashes are backslash-escaped, line breaks become spaces.
+    fn string(&mut self, key: &str, value: &str) {
+        let escaped = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace(['\n', '\r'], " ");
+        self.push(key, format_args!("\"{}\"", escaped));
+    }
+}
+
+/// Escapes a tag key, tag value or field key: commas, equal signs and spaces are
+/// backslash-escaped, and line breaks (which cannot be escaped) become spaces.
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ',' | '=' | ' ' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' | '\r' => escaped.push_str("\\ "),
+            // A trailing backslash would escape the following separator
+            '\\' => escaped.push_str("\\\\"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Exporter for InfluxExporter {
+    fn exporter_type(&self) -> ExporterType {
+        ExporterType::Influx
+    }
+
+    fn add_record(&mut self, record: Record) -> Result<(), Box<dyn Error>> {
+        let line = self.record_line(&record);
+        self.write_line(line)
+    }
+
+    fn export(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        let line = self.summary_line(summary)?;
+        self.write_line(line)?;
+        self.flush()?;
+        eprintln!("\n[INFLUX EXPORT]");
+        eprintln!("Points written to: {}", self.destination);
+        Ok(())
+    }
+
+    fn export_line(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn end_interval(&mut self) -> Result<(), Box<dyn Error>> {
+        // An endpoint that is briefly unreachable must not end the measurement
+        if let Err(e) = self.flush() {
+            eprintln!(
+                "InfluxDB write to {} failed, will retry: {}",
+                self.destination, e
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::{Scope, testing};
+    use crate::summary::EnergyMeter;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// A run whose command and labels need escaping.
+    fn run() -> RunInfo {
+        let mut run = testing::run();
+        run.labels.insert("team name".into(), "a,b=c".into());
+        // Cannot replace the host tag
+        run.labels.insert("host".into(), "other".into());
+        run
+    }
+
+    #[test]
+    fn sends_escaped_lines_over_udp() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let destination = format!("udp://{}", listener.local_addr().unwrap());
+        let mut exporter = InfluxExporter::new(destination, run()).unwrap();
+        let receive = || {
+            let mut buf = [0; 2048];
+            let len = listener.recv(&mut buf).unwrap();
+            String::from_utf8(buf[..len].to_vec()).unwrap()
+        };
+
+        let mut meter = EnergyMeter::new();
+        for id in 1..=2 {
+            let mut record = testing::record(id);
+            record.wall_power_w = Some(12.0);
+            meter.add_record(&record);
+            exporter.add_record(record).unwrap();
+        }
+        receive();
+        assert_eq!(
+            receive(),
+            "ecocode,command=make\\ test,host=lab-1,run=1700000000000-42,team\\ name=a\\,b\\=c,\
+             pid=42,scope=total cpu_usage=50,cpu_power_w=10,cpu_joules=20,\
+             estimated_wall_power_w=12,estimated_wall_joules=24,counter_wrapped=false \
+             1700000002000000000"
+        );
+
+        exporter.export(&meter.summary(42, 0)).unwrap();
+        let summary = receive();
+        assert!(summary.starts_with(
+            "ecocode_run,command=make\\ test,host=lab-1,run=1700000000000-42,\
+             team\\ name=a\\,b\\=c exit_code=0i,duration_s=2,samples=2i,cpu_joules=20,"
+        ));
+        assert!(summary.contains(",total_joules=20,estimated_wall_joules=24,mean_power_w=10,"));
+        // Timestamped now, in nanoseconds
+        let timestamp: u128 = summary.rsplit(' ').next().unwrap().parse().unwrap();
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
+        assert!(now.unwrap().as_nanos() - timestamp < 5_000_000_000);
+    }
+
+    #[test]
+    fn posts_one_batch_per_interval_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let destination = format!("http://{}/api/v2/write", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            [0, 1].map(|_| {
+                let (head, body) = testing::accept_request(&listener);
+                (head, String::from_utf8(body).unwrap())
+            })
+        });
+
+        let mut exporter = InfluxExporter::new(destination, testing::run()).unwrap();
+        let mut meter = EnergyMeter::new();
+        let total = testing::record(1);
+        meter.add_record(&total);
+        let mut process = testing::record(1);
+        process.scope = Scope::Process;
+        process.pid = 43;
+        for record in [total, process] {
+            exporter.add_record(record).unwrap();
+            exporter.export_line().unwrap();
+        }
+        exporter.end_interval().unwrap();
+        exporter.export(&meter.summary(42, 0)).unwrap();
+
+        let [(head, batch), (_, summary)] = server.join().unwrap();
+        assert!(head.starts_with("POST /api/v2/write HTTP/1.1\r\n"));
+        let lines: Vec<&str> = batch.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(",pid=42,scope=total "));
+        assert!(lines[1].contains(",pid=43,scope=process "));
+        assert!(summary.starts_with("ecocode_run,"));
+        assert_eq!(summary.lines().count(), 1);
+    }
+
+    #[test]
+    fn a_failed_run_reports_its_error_instead_of_an_exit_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.lp");
+        let exporter =
+            InfluxExporter::new(path.to_string_lossy().into_owned(), testing::run()).unwrap();
+        let summary = EnergyMeter::new().failed_summary(
+            42,
+            None,
+            "reading \"cpu.stat\" failed:\nC:\\dev".to_string(),
+        );
+        let line = exporter.summary_line(&summary).unwrap();
+        assert!(!line.contains("exit_code"));
+        assert!(line.contains(r#" error="reading \"cpu.stat\" failed: C:\\dev",duration_s=0,"#));
+    }
+}
@@ -1,9 +1,10 @@
 use serde_json;
 use std::error::Error;
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
 
-use crate::exporter::{Exporter, ExporterType, Record};
+use crate::exporter::{Exporter, ExporterType, Record, summary_path};
+use crate::summary::Summary;
 
 pub struct JsonExporter {
     pub file_path: String,
@@ -42,15 +43,32 @@ impl Exporter for JsonExporter {
         let json = serde_json::to_string(&record)?;
         self.writer.write_all(json.as_bytes())?;
 
+        // Close the array after every record so the file stays valid JSON even if
+        // EcoCode is killed; the next record overwrites the closing bracket
+        self.writer.write_all(b"]")?;
+        self.writer.flush()?;
+        self.writer.seek(SeekFrom::Current(-1))?;
+
         Ok(())
     }
-    fn export(&mut self) -> Result<(), Box<dyn Error>> {
-        // Write the closing bracket for the JSON array
-        self.writer.write_all(b"]")?;
+    fn export(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        // The closing bracket is already there, unless no record was written
+        if self.first_record {
+            self.writer.write_all(b"[]")?;
+        } else {
+            self.writer.seek(SeekFrom::Current(1))?;
+        }
         self.writer.flush()?;
 
-        println!("\n[JSON EXPORT]");
-        println!("Records found in  File: {}", self.file_path);
+        // The records file stays a plain array, the summary goes next to it
+        let path = summary_path(&self.file_path);
+        let mut summary_writer = BufWriter::new(File::create(&path)?);
+        serde_json::to_writer_pretty(&mut summary_writer, summary)?;
+        summary_writer.flush()?;
+
+        eprintln!("\n[JSON EXPORT]");
+        eprintln!("Records found in File: {}", self.file_path);
+        eprintln!("Summary found in File: {}", path.display());
         Ok(())
     }
     fn export_line(&mut self) -> Result<(), Box<dyn std::error::Error>> {
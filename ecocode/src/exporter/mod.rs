@@ -4,61 +4,246 @@
 //! - [`terminal`] — pretty-printed table to stdout
 //! - [`csv`] — comma-separated values file
 //! - [`json`] — JSON array file
+//! - [`ndjson`] — newline-delimited JSON to a file, stdout or a Unix socket
+//! - [`influx`] — InfluxDB line protocol to a file, stdout, UDP or HTTP
+//! - `columnar` — typed Parquet or Arrow IPC file (behind the `columnar` feature)
 //! - [`sqlite`] — SQLite database
+//! - `otlp` — OpenTelemetry metrics pushed to a collector (behind the `otlp` feature)
 //! - [`prometheus`] — Prometheus metrics endpoint via HTTP
+//! - [`textfile`] — Prometheus metrics file for the node_exporter textfile collector
+//! - [`tui`] — interactive dashboard in the terminal
+//!
+//! Several exporters can be combined in one run through [`composite`].
 
+#[cfg(feature = "columnar")]
+pub mod columnar;
+pub mod composite;
 pub mod csv;
+pub mod influx;
 pub mod json;
+pub mod ndjson;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod prometheus;
 pub mod sqlite;
 pub mod terminal;
+pub mod textfile;
+pub mod tui;
 
+use crate::summary::Summary;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Destination that selects stdout, for the exporters that stream to it.
+pub const STDOUT: &str = "-";
+
+/// The original stdout, once [`redirect_stdout`] has moved everything else away.
+static RECORD_STDOUT: OnceLock<File> = OnceLock::new();
+
+/// Keeps the original stdout for the records and points file descriptor 1 at stderr,
+/// so EcoCode's own messages (and a spawned command's output) cannot interleave with
+/// the stream. Must run before anything is printed.
+pub fn redirect_stdout() -> io::Result<()> {
+    io::stdout().flush()?;
+    // SAFETY: plain descriptor syscalls; the duplicate is owned by the new File.
+    let records = unsafe {
+        let fd = libc::dup(libc::STDOUT_FILENO);
+        if fd < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        File::from_raw_fd(fd)
+    };
+    let _ = RECORD_STDOUT.set(records);
+    Ok(())
+}
+
+/// A handle on the original stdout kept by [`redirect_stdout`].
+pub fn record_stdout() -> Result<File, Box<dyn std::error::Error>> {
+    let stdout = RECORD_STDOUT
+        .get()
+        .ok_or("stdout was not redirected for the record stream")?;
+    Ok(stdout.try_clone()?)
+}
+
+/// What a record's measurements cover.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// The whole monitored process tree; `pid` is its root.
+    Total,
+    /// A single process of the tree (optional per-process rows).
+    Process,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Total => "total",
+            Scope::Process => "process",
+        }
+    }
+}
 
 /// Represents a single measurement record
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Record {
     pub id: u32,
     pub pid: u32,
-    pub timestamp: i64,  // milliseconds since epoch
-    pub cpu_usage: f64,  // percentage (0-100)
-    pub cpu_energy: f64, // watts
-    pub gpu_usage: f64,  // percentage (0-100)
-    pub gpu_energy: f64, // watts
+    pub scope: Scope,
+    /// Parent PID of a per-process row, None for totals and in older recordings.
+    #[serde(default)]
+    pub ppid: Option<u32>,
+    /// Process name of a per-process row, None for totals and in older recordings.
+    #[serde(default)]
+    pub name: Option<String>,
+    pub timestamp: i64,     // milliseconds since epoch
+    pub interval_secs: f64, // measured length of the sample
+    pub cpu_usage: f64,     // percentage (0-100)
+    /// Watts, total of the disjoint CPU domains; `cpu_energy` in older recordings.
+    #[serde(alias = "cpu_energy")]
+    pub cpu_power_w: f64,
+    pub cpu_domains: BTreeMap<String, f64>, // watts per CPU sensor (e.g. rapl:package-0)
+    pub gpu_usage: Option<f64>,             // percentage (0-100), None without a GPU
+    /// Watts, None without a GPU; `gpu_energy` in older recordings.
+    #[serde(alias = "gpu_energy")]
+    pub gpu_power_w: Option<f64>,
+    /// Watts, None without a system-level sensor; `system_energy` in older recordings.
+    #[serde(alias = "system_energy")]
+    pub system_power_w: Option<f64>,
+    /// Estimated watts at the wall (see [`crate::facility`]), None without a wall-power
+    /// conversion and in older recordings.
+    #[serde(default)]
+    pub wall_power_w: Option<f64>,
+    /// Estimated watts of the facility, wall power times the PUE.
+    #[serde(default)]
+    pub facility_power_w: Option<f64>,
+    /// Emissions of the sample's grid energy in gCO₂e, None without
+    /// `--carbon-intensity` and in older recordings.
+    #[serde(default)]
+    pub carbon_g: Option<f64>,
+    /// Cost of the sample's grid energy in the run's currency, None without
+    /// `--energy-price` and in older recordings.
+    #[serde(default)]
+    pub cost: Option<f64>,
+    /// Water consumed for the sample's energy in litres (see [`crate::water`]), None
+    /// without `--wue` or `--water-intensity` and in older recordings.
+    #[serde(default)]
+    pub water_l: Option<f64>,
+    pub counter_wrapped: bool, // an energy counter wrapped around or was reset during this sample
 }
 
 impl Record {
-    pub fn new(
-        id: u32,
-        pid: u32,
-        timestamp: i64,
-        cpu_usage: f64,
-        cpu_energy: f64,
-        gpu_usage: f64,
-        gpu_energy: f64,
-    ) -> Record {
-        Record {
-            id,
-            pid,
-            timestamp,
-            cpu_usage,
-            cpu_energy,
-            gpu_usage,
-            gpu_energy,
-        }
+    /// Column names matching [`Record::to_vec`], including one column per CPU domain.
+    pub fn headers(&self) -> Vec<String> {
+        let mut headers: Vec<String> = [
+            "ID",
+            "PID",
+            "Scope",
+            "PPID",
+            "Name",
+            "Timestamp",
+            "Interval(s)",
+            "CPU%",
+            "CPU(W)",
+        ]
+        .iter()
+        .map(|h| h.to_string())
+        .collect();
+        headers.extend(self.cpu_domains.keys().map(|d| format!("CPU[{}](W)", d)));
+        headers.extend(
+            [
+                "GPU%",
+                "GPU(W)",
+                "SYS(W)",
+                "Wall(W)",
+                "Facility(W)",
+                "CO2e(g)",
+                "Cost",
+                "Water(L)",
+                "Wrapped",
+            ]
+            .iter()
+            .map(|h| h.to_string()),
+        );
+        headers
     }
+
     pub fn to_vec(&self) -> Vec<String> {
-        vec![
+        let mut values = vec![
             self.id.to_string(),
             self.pid.to_string(),
+            self.scope.as_str().to_string(),
+            self.ppid.map(|p| p.to_string()).unwrap_or_default(),
+            self.name.clone().unwrap_or_default(),
             self.timestamp.to_string(),
+            self.interval_secs.to_string(),
             self.cpu_usage.to_string(),
-            self.cpu_energy.to_string(),
-            self.gpu_usage.to_string(),
-            self.gpu_energy.to_string(),
-        ]
+            self.cpu_power_w.to_string(),
+        ];
+        values.extend(self.cpu_domains.values().map(|w| w.to_string()));
+        values.extend([
+            opt_to_string(self.gpu_usage),
+            opt_to_string(self.gpu_power_w),
+            opt_to_string(self.system_power_w),
+            opt_to_string(self.wall_power_w),
+            opt_to_string(self.facility_power_w),
+            opt_to_string(self.carbon_g),
+            opt_to_string(self.cost),
+            opt_to_string(self.water_l),
+            self.counter_wrapped.to_string(),
+        ]);
+        values
+    }
+
+    /// Energy in joules of `power_w`, one of the powers of the sample. Power is the
+    /// counter delta over the measured interval, so this recovers the exact energy.
+    pub fn joules(&self, power_w: f64) -> f64 {
+        power_w * self.interval_secs
+    }
+
+    /// CPU + GPU energy of the sample in joules, like the summary's total.
+    pub fn energy_j(&self) -> f64 {
+        self.joules(self.cpu_power_w + self.gpu_power_w.unwrap_or(0.0))
+    }
+
+    /// Estimated wall energy of the sample in joules.
+    pub fn wall_energy_j(&self) -> Option<f64> {
+        self.wall_power_w.map(|w| self.joules(w))
+    }
+
+    /// Estimated facility energy of the sample in joules.
+    pub fn facility_energy_j(&self) -> Option<f64> {
+        self.facility_power_w.map(|w| self.joules(w))
+    }
+
+    /// Energy of the sample drawn from the grid, in joules: the facility energy when it
+    /// is estimated, otherwise the CPU + GPU energy.
+    pub fn grid_energy_j(&self) -> f64 {
+        self.facility_energy_j().unwrap_or(self.energy_j())
     }
 }
 
+/// Path of the file holding the run summary next to the records file `file_path`,
+/// e.g. `run.csv` -> `run.summary.csv`.
+pub fn summary_path(file_path: &str) -> PathBuf {
+    let path = Path::new(file_path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(ext) => path.with_file_name(format!("{}.summary.{}", stem, ext.to_string_lossy())),
+        None => path.with_file_name(format!("{}.summary", stem)),
+    }
+}
+
+/// Formats an optional measurement, leaving the cell empty when it is absent.
+fn opt_to_string(value: Option<f64>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ExporterType {
     Terminal,
@@ -66,12 +251,130 @@ pub enum ExporterType {
     Json,
     Sqlite,
     Prometheus,
+    Textfile,
+    Composite,
+    Ndjson,
+    Influx,
+    Tui,
+    #[cfg(feature = "columnar")]
+    Parquet,
+    #[cfg(feature = "columnar")]
+    ArrowIpc,
+    #[cfg(feature = "otlp")]
+    Otlp,
+}
+
+/// Identifies a run and describes where it ran.
+#[derive(Clone, Debug)]
+pub struct RunInfo {
+    /// Unique per run: start time in milliseconds and EcoCode's own PID.
+    pub id: String,
+    /// What is measured: the command line, or how the running target was selected.
+    pub command: String,
+    /// Argument vector of a spawned command, empty when attaching to running processes.
+    pub args: Vec<String>,
+    pub started_at: i64, // milliseconds since epoch
+    pub host: String,
+    pub hardware: Hardware,
+    /// User-defined `--label KEY=VALUE` pairs.
+    pub labels: BTreeMap<String, String>,
+    /// Currency of the record costs, None without `--energy-price`.
+    pub currency: Option<String>,
+}
+
+/// The measuring host's hardware, as far as it matters for the results.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Hardware {
+    pub cpu: String,
+    pub cores: usize,
+    pub sensors: Vec<String>,
 }
 
 /// Trait for different export formats
 pub trait Exporter {
     fn exporter_type(&self) -> ExporterType; // Returns "terminal", "csv", "json", etc.
     fn add_record(&mut self, record: Record) -> Result<(), Box<dyn std::error::Error>>;
-    fn export(&mut self) -> Result<(), Box<dyn std::error::Error>>;
+    /// Finishes the output at the end of the run, including the run's [`Summary`].
+    fn export(&mut self, summary: &Summary) -> Result<(), Box<dyn std::error::Error>>;
     fn export_line(&mut self) -> Result<(), Box<dyn std::error::Error>>;
+    /// Called once every record of a sampling interval has been added, for the exporters
+    /// that send them in batches.
+    fn end_interval(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+}
+
+/// Runs and records for the exporter tests.
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+
+    pub fn run() -> RunInfo {
+        RunInfo {
+            id: "1700000000000-42".to_string(),
+            command: "make test".to_string(),
+            args: vec!["make".to_string(), "test".to_string()],
+            started_at: 1_700_000_000_000,
+            host: "lab-1".to_string(),
+            hardware: Hardware::default(),
+            labels: BTreeMap::new(),
+            currency: None,
+        }
+    }
+
+    /// A one-second sample of the whole target at 10 W of CPU power.
+    pub fn record(id: u32) -> Record {
+        Record {
+            id,
+            pid: 42,
+            scope: Scope::Total,
+            ppid: None,
+            name: None,
+            timestamp: 1_700_000_000_000 + id as i64 * 1000,
+            interval_secs: 1.0,
+            cpu_usage: 50.0,
+            cpu_power_w: 10.0,
+            cpu_domains: BTreeMap::from([("rapl:package-0".to_string(), 10.0)]),
+            gpu_usage: None,
+            gpu_power_w: None,
+            system_power_w: None,
+            wall_power_w: None,
+            facility_power_w: None,
+            carbon_g: None,
+            cost: None,
+            water_l: None,
+            counter_wrapped: false,
+        }
+    }
+
+    /// Accepts one HTTP request on `listener`, answers it with an empty 200 response and
+    /// returns its request line and headers, and its body.
+    pub fn accept_request(listener: &TcpListener) -> (String, Vec<u8>) {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut head = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some((name, value)) = line.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                length = value.trim().parse().unwrap();
+            }
+            if line == "\r\n" {
+                break;
+            }
+            head.push_str(&line);
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        (head, body)
+    }
 }
@@ -0,0 +1,190 @@
+//! NDJSON exporter — streams one self-contained JSON record per line.
+//!
+//! Every line is flushed as soon as it is written, so other tools can follow the
+//! measurements live. The destination is a file, stdout (`-`) or a Unix domain socket
+//! (`unix:<path>`) that any number of readers can connect to. The run summary is the
+//! last line, as `{"summary": {...}}`.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::exporter::{Exporter, ExporterType, Record, STDOUT, record_stdout};
+use crate::summary::Summary;
+
+/// Prefix of a Unix domain socket destination.
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// How long a socket reader may block a write before it is disconnected.
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+enum Sink {
+    Stream(BufWriter<File>),
+    Socket {
+        path: PathBuf,
+        listener: UnixListener,
+        readers: Vec<UnixStream>,
+    },
+}
+
+/// Writes every record as one JSON line.
+pub struct NdjsonExporter {
+    destination: String,
+    sink: Sink,
+}
+
+#[derive(Serialize)]
+struct SummaryLine<'a> {
+    summary: &'a Summary,
+}
+
+impl NdjsonExporter {
+    pub fn new(destination: String) -> Result<NdjsonExporter, Box<dyn Error>> {
+        let sink = if destination == STDOUT {
+            Sink::Stream(BufWriter::new(record_stdout()?))
+        } else if let Some(path) = destination.strip_prefix(UNIX_PREFIX) {
+            let path = PathBuf::from(path);
+            // A socket left behind by an earlier run would make bind fail
+            if fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_socket()) {
+                fs::remove_file(&path)?;
+            }
+            let listener = UnixListener::bind(&path)?;
+            listener.set_nonblocking(true)?;
+            Sink::Socket {
+                path,
+                listener,
+                readers: Vec::new(),
+            }
+        } else {
+            Sink::Stream(BufWriter::new(File::create(&destination)?))
+        };
+        Ok(NdjsonExporter { destination, sink })
+    }
+
+    fn write_line(&mut self, value: &impl Serialize) -> Result<(), Box<dyn Error>> {
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+        match &mut self.sink {
+            Sink::Stream(writer) => {
+                writer.write_all(&line)?;
+                writer.flush()?;
+            }
+            Sink::Socket {
+                listener, readers, ..
+            } => {
+                accept_readers(listener, readers)?;
+                // Readers that went away or cannot keep up are dropped
+                readers.retain_mut(|reader| reader.write_all(&line).is_ok());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Accepts every reader waiting on the socket.
+fn accept_readers(listener: &UnixListener, readers: &mut Vec<UnixStream>) -> io::Result<()> {
+    loop {
+        match listener.accept() {
+            Ok((reader, _)) => {
+                reader.set_nonblocking(false)?;
+                reader.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT))?;
+                readers.push(reader);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl Exporter for NdjsonExporter {
+    fn exporter_type(&self) -> ExporterType {
+        ExporterType::Ndjson
+    }
+
+    fn add_record(&mut self, record: Record) -> Result<(), Box<dyn Error>> {
+        self.write_line(&record)
+    }
+
+    fn export(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        self.write_line(&SummaryLine { summary })?;
+        eprintln!("\n[NDJSON EXPORT]");
+        eprintln!("Records streamed to: {}", self.destination);
+        Ok(())
+    }
+
+    fn export_line(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+impl Drop for NdjsonExporter {
+    fn drop(&mut self) {
+        if let Sink::Socket { path, .. } = &self.sink {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::testing;
+    use crate::summary::EnergyMeter;
+    use serde_json::Value;
+    use std::io::Read;
+
+    /// Streams two records and the summary of a run through `exporter`.
+    fn stream(mut exporter: NdjsonExporter) {
+        let mut meter = EnergyMeter::new();
+        for id in [1, 2] {
+            let record = testing::record(id);
+            meter.add_record(&record);
+            exporter.add_record(record).unwrap();
+            exporter.export_line().unwrap();
+        }
+        exporter.export(&meter.summary(42, 0)).unwrap();
+    }
+
+    fn check_lines(text: &str) {
+        let lines: Vec<Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["id"], 1);
+        assert_eq!(lines[1]["id"], 2);
+        assert_eq!(lines[1]["cpu_domains"]["rapl:package-0"], 10.0);
+        assert_eq!(lines[2]["summary"]["exit_code"], 0);
+        assert_eq!(lines[2]["summary"]["duration_secs"], 2.0);
+    }
+
+    #[test]
+    fn writes_one_line_per_record_to_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.ndjson");
+        stream(NdjsonExporter::new(path.to_string_lossy().into_owned()).unwrap());
+        check_lines(&fs::read_to_string(&path).unwrap());
+    }
+
+    #[test]
+    fn streams_to_unix_socket_readers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.sock");
+        // A socket left behind by an earlier run is replaced
+        drop(UnixListener::bind(&path).unwrap());
+        let exporter = NdjsonExporter::new(format!("{}{}", UNIX_PREFIX, path.display())).unwrap();
+        let mut reader = UnixStream::connect(&path).unwrap();
+        stream(exporter);
+
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        check_lines(&text);
+        assert!(!path.exists());
+    }
+}
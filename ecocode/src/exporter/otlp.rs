@@ -0,0 +1,732 @@
+//! OTLP exporter — pushes the measurements as OpenTelemetry metrics to a collector.
+//!
+//! Metrics are sent over OTLP/HTTP with protobuf encoding, once per sampling interval and
+//! a last time at the end of the run. The resource describes the host, the target
+//! command and the run ID; every data point carries the PID and scope of its record and
+//! the component (`cpu`, `gpu`, `system`) it covers:
+//! - `ecocode.power` — gauge, power attributed during the last sample (W)
+//! - `ecocode.energy` — cumulative monotonic sum, energy attributed since the run
+//!   started (J)
+//! - `ecocode.estimated_power` and `ecocode.estimated_energy` — the same for the `wall`
+//!   and `facility` power estimated with the PSU and PUE options, by `kind` instead of
+//!   component as they include the measured components
+//! - `ecocode.utilization` — gauge, share of the component used by the target (1)
+//! - `ecocode.carbon` — cumulative monotonic sum, emissions of the attributed energy
+//!   since the run started (g), with `--carbon-intensity` and without a component
+//! - `ecocode.cost` — cumulative monotonic sum, cost of the attributed energy since the
+//!   run started (e.g. `{EUR}`), with `--energy-price` and a `currency` attribute
+//!   instead of the component
+//! - `ecocode.water` — cumulative monotonic sum, water consumed for the attributed
+//!   energy since the run started (L), with `--wue` or `--water-intensity` and without
+//!   a component
+//!
+//! The last push also carries the run summary as gauges without PID and scope:
+//! `ecocode.run.duration` (s), `ecocode.run.power` (W, by `stat`: mean, peak and p95),
+//! `ecocode.run.energy` (J, by component), `ecocode.run.estimated_energy` (J, by kind),
+//! and the run's `ecocode.run.carbon`,
+//! `ecocode.run.cost` and `ecocode.run.water` totals when they are estimated.
+//!
+//! The destination is the collector's base URL (`/v1/metrics` is appended) or the full
+//! metrics URL. Headers, e.g. for authentication, are taken from
+//! `OTEL_EXPORTER_OTLP_HEADERS` as `key=value` pairs separated by commas.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+use opentelemetry_proto::tonic::collector::metrics::v1::{
+    ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+};
+use opentelemetry_proto::tonic::common::v1::{
+    AnyValue, ArrayValue, InstrumentationScope, KeyValue, any_value,
+};
+use opentelemetry_proto::tonic::metrics::v1::{
+    AggregationTemporality, Gauge, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics, Sum,
+    metric, number_data_point,
+};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use prost::Message;
+use ureq::Agent;
+
+use crate::exporter::{Exporter, ExporterType, Record, RunInfo};
+use crate::summary::Summary;
+
+/// Collector used when the output has no destination: the standard local OTLP/HTTP port.
+pub const DEFAULT_ENDPOINT: &str = "http://localhost:4318";
+
+/// Environment variable with extra request headers, as defined by the OTLP exporter spec.
+pub const HEADERS_VAR: &str = "OTEL_EXPORTER_OTLP_HEADERS";
+
+/// Path of the metrics service below the collector's base URL.
+const METRICS_PATH: &str = "/v1/metrics";
+
+/// Content type of a protobuf-encoded OTLP request.
+const CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// Longest a push may take before it is given up.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Latest values of one (scope, PID) series.
+#[derive(Default)]
+struct Series {
+    time_unix_nano: u64,
+    utilization: BTreeMap<&'static str, f64>,
+    power_w: BTreeMap<&'static str, f64>,
+    energy_j: BTreeMap<&'static str, f64>,
+    /// Estimated wall and facility power and energy, by kind.
+    estimated_power_w: BTreeMap<&'static str, f64>,
+    estimated_energy_j: BTreeMap<&'static str, f64>,
+    carbon_g: Option<f64>,
+    cost: Option<f64>,
+    water_l: Option<f64>,
+}
+
+/// Pushes the metrics of a run to an OTLP/HTTP collector.
+pub struct OtlpExporter {
+    agent: Agent,
+    url: String,
+    headers: Vec<(String, String)>,
+    resource: Resource,
+    currency: Option<String>,
+    start_time_unix_nano: u64,
+    series: BTreeMap<(&'static str, u32), Series>,
+    /// Set at the end of the run, for the last push.
+    summary: Option<Summary>,
+    /// Whether a record arrived since the last push.
+    updated: bool,
+}
+
+impl OtlpExporter {
+    pub fn new(endpoint: &str, run: &RunInfo) -> Result<OtlpExporter, Box<dyn Error>> {
+        if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+            return Err(format!("OTLP endpoint {} is not an http(s) URL", endpoint).into());
+        }
+        let endpoint = endpoint.trim_end_matches('/');
+        let url = if endpoint.ends_with(METRICS_PATH) {
+            endpoint.to_string()
+        } else {
+            format!("{}{}", endpoint, METRICS_PATH)
+        };
+        let headers = match std::env::var(HEADERS_VAR) {
+            Ok(value) => parse_headers(&value)?,
+            Err(_) => Vec::new(),
+        };
+        let agent = Agent::config_builder()
+            .timeout_global(Some(HTTP_TIMEOUT))
+            .build()
+            .into();
+        Ok(OtlpExporter {
+            agent,
+            url,
+            headers,
+            resource: resource(run),
+            currency: run.currency.clone(),
+            start_time_unix_nano: run.started_at as u64 * 1_000_000,
+            series: BTreeMap::new(),
+            summary: None,
+            updated: false,
+        })
+    }
+
+    /// The current state of every series as OTLP metrics.
+    fn metrics(&self) -> Vec<Metric> {
+        let point =
+            |key: &(&str, u32), series: &Series, label: Option<(&str, &str)>, value: f64| {
+                let mut attributes = vec![
+                    attribute("pid", any_value::Value::IntValue(key.1.into())),
+                    string_attribute("scope", key.0),
+                ];
+                if let Some((name, value)) = label {
+                    attributes.push(string_attribute(name, value));
+                }
+                NumberDataPoint {
+                    attributes,
+                    start_time_unix_nano: self.start_time_unix_nano,
+                    time_unix_nano: series.time_unix_nano,
+                    value: Some(number_data_point::Value::AsDouble(value)),
+                    ..Default::default()
+                }
+            };
+        let points = |label: &'static str, values: fn(&Series) -> &BTreeMap<&'static str, f64>| {
+            self.series
+                .iter()
+                .flat_map(|(key, series)| {
+                    values(series)
+                        .iter()
+                        .map(move |(name, value)| point(key, series, Some((label, name)), *value))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut metrics = vec![
+            Metric {
+                name: "ecocode.power".into(),
+                description: "Power attributed to the target during the last sample.".into(),
+                unit: "W".into(),
+                data: Some(metric::Data::Gauge(Gauge {
+                    data_points: points("component", |s| &s.power_w),
+                })),
+                ..Default::default()
+            },
+            Metric {
+                name: "ecocode.energy".into(),
+                description: "Energy attributed to the target since the run started.".into(),
+                unit: "J".into(),
+                data: Some(metric::Data::Sum(Sum {
+                    data_points: points("component", |s| &s.energy_j),
+                    aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                    is_monotonic: true,
+                })),
+                ..Default::default()
+            },
+            Metric {
+                name: "ecocode.utilization".into(),
+                description: "Share of the component used by the target during the last sample."
+                    .into(),
+                unit: "1".into(),
+                data: Some(metric::Data::Gauge(Gauge {
+                    data_points: points("component", |s| &s.utilization),
+                })),
+                ..Default::default()
+            },
+        ];
+
+        let estimated_power = points("kind", |s| &s.estimated_power_w);
+        if !estimated_power.is_empty() {
+            metrics.extend([
+                Metric {
+                    name: "ecocode.estimated_power".into(),
+                    description: "Estimated wall or facility power of the target during the \
+                                  last sample."
+                        .into(),
+                    unit: "W".into(),
+                    data: Some(metric::Data::Gauge(Gauge {
+                        data_points: estimated_power,
+                    })),
+                    ..Default::default()
+                },
+                Metric {
+                    name: "ecocode.estimated_energy".into(),
+                    description: "Estimated wall or facility energy of the target since the \
+                                  run started."
+                        .into(),
+                    unit: "J".into(),
+                    data: Some(metric::Data::Sum(Sum {
+                        data_points: points("kind", |s| &s.estimated_energy_j),
+                        aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                        is_monotonic: true,
+                    })),
+                    ..Default::default()
+                },
+            ]);
+        }
+
+        let carbon: Vec<NumberDataPoint> = self
+            .series
+            .iter()
+            .filter_map(|(key, series)| {
+                series.carbon_g.map(|grams| point(key, series, None, grams))
+            })
+            .collect();
+        if !carbon.is_empty() {
+            metrics.push(Metric {
+                name: "ecocode.carbon".into(),
+                description: "Emissions of the energy attributed to the target since the run \
+                              started."
+                    .into(),
+                unit: "g".into(),
+                data: Some(metric::Data::Sum(Sum {
+                    data_points: carbon,
+                    aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                    is_monotonic: true,
+                })),
+                ..Default::default()
+            });
+        }
+
+        let currency = self.currency.as_deref().unwrap_or_default();
+        let cost: Vec<NumberDataPoint> = self
+            .series
+            .iter()
+            .filter_map(|(key, series)| {
+                let label = Some(("currency", currency));
+                series.cost.map(|cost| point(key, series, label, cost))
+            })
+            .collect();
+        if !cost.is_empty() {
+            metrics.push(Metric {
+                name: "ecocode.cost".into(),
+                description: "Cost of the energy attributed to the target since the run started."
+                    .into(),
+                unit: format!("{{{}}}", currency),
+                data: Some(metric::Data::Sum(Sum {
+                    data_points: cost,
+                    aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                    is_monotonic: true,
+                })),
+                ..Default::default()
+            });
+        }
+
+        let water: Vec<NumberDataPoint> = self
+            .series
+            .iter()
+            .filter_map(|(key, series)| {
+                series
+                    .water_l
+                    .map(|litres| point(key, series, None, litres))
+            })
+            .collect();
+        if !water.is_empty() {
+            metrics.push(Metric {
+                name: "ecocode.water".into(),
+                description: "Water consumed for the energy attributed to the target since the \
+                              run started."
+                    .into(),
+                unit: "L".into(),
+                data: Some(metric::Data::Sum(Sum {
+                    data_points: water,
+                    aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                    is_monotonic: true,
+                })),
+                ..Default::default()
+            });
+        }
+
+        if let Some(summary) = &self.summary {
+            metrics.extend(self.summary_metrics(summary));
+        }
+        metrics
+    }
+
+    /// The run summary as gauges.
+    fn summary_metrics(&self, summary: &Summary) -> Vec<Metric> {
+        let time_unix_nano = self.start_time_unix_nano + (summary.duration_secs * 1e9) as u64;
+        let point = |label: Option<(&str, &str)>, value: f64| NumberDataPoint {
+            attributes: label
+                .map(|(name, value)| string_attribute(name, value))
+                .into_iter()
+                .collect(),
+            start_time_unix_nano: self.start_time_unix_nano,
+            time_unix_nano,
+            value: Some(number_data_point::Value::AsDouble(value)),
+            ..Default::default()
+        };
+        let gauge = |name: &str, description: &str, unit: &str, data_points| Metric {
+            name: name.into(),
+            description: description.into(),
+            unit: unit.into(),
+            data: Some(metric::Data::Gauge(Gauge { data_points })),
+            ..Default::default()
+        };
+
+        let stats = [
+            ("mean", summary.mean_power_w),
+            ("peak", summary.peak_power_w),
+            ("p95", summary.p95_power_w),
+        ];
+        let components = [
+            ("cpu", Some(summary.cpu_energy)),
+            ("gpu", summary.gpu_energy),
+            ("system", summary.system_energy),
+        ];
+        let mut metrics = vec![
+            gauge(
+                "ecocode.run.duration",
+                "Duration of the finished run.",
+                "s",
+                vec![point(None, summary.duration_secs)],
+            ),
+            gauge(
+                "ecocode.run.power",
+                "Mean, peak and p95 total power of the finished run.",
+                "W",
+                stats
+                    .iter()
+                    .map(|(stat, value)| point(Some(("stat", stat)), *value))
+                    .collect(),
+            ),
+            gauge(
+                "ecocode.run.energy",
+                "Energy attributed to the target over the finished run.",
+                "J",
+                components
+                    .iter()
+                    .filter_map(|(component, energy)| {
+                        energy.map(|e| point(Some(("component", component)), e.joules))
+                    })
+                    .collect(),
+            ),
+        ];
+        let estimates = [
+            ("wall", summary.wall_energy),
+            ("facility", summary.facility_energy),
+        ];
+        let estimated: Vec<NumberDataPoint> = estimates
+            .iter()
+            .filter_map(|(kind, energy)| energy.map(|e| point(Some(("kind", kind)), e.joules)))
+            .collect();
+        if !estimated.is_empty() {
+            metrics.push(gauge(
+                "ecocode.run.estimated_energy",
+                "Estimated wall or facility energy of the finished run.",
+                "J",
+                estimated,
+            ));
+        }
+        if let Some(carbon) = summary.carbon {
+            metrics.push(gauge(
+                "ecocode.run.carbon",
+                "Emissions of the energy the finished run drew from the grid.",
+                "g",
+                vec![point(None, carbon.grams)],
+            ));
+        }
+        if let Some(cost) = &summary.cost {
+            metrics.push(gauge(
+                "ecocode.run.cost",
+                "Cost of the energy the finished run drew from the grid.",
+                &format!("{{{}}}", cost.currency),
+                vec![point(Some(("currency", &cost.currency)), cost.amount)],
+            ));
+        }
+        if let Some(water) = summary.water {
+            metrics.push(gauge(
+                "ecocode.run.water",
+                "Water consumed for the energy of the finished run.",
+                "L",
+                vec![point(None, water.litres)],
+            ));
+        }
+        metrics
+    }
+
+    /// Sends the current state of every series to the collector.
+    fn push(&mut self) -> Result<(), Box<dyn Error>> {
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(self.resource.clone()),
+                scope_metrics: vec![ScopeMetrics {
+                    scope: Some(InstrumentationScope {
+                        name: env!("CARGO_PKG_NAME").into(),
+                        version: env!("CARGO_PKG_VERSION").into(),
+                        ..Default::default()
+                    }),
+                    metrics: self.metrics(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        let mut post = self
+            .agent
+            .post(self.url.as_str())
+            .content_type(CONTENT_TYPE);
+        for (name, value) in &self.headers {
+            post = post.header(name.as_str(), value.as_str());
+        }
+        let mut response = post.send(&request.encode_to_vec()[..])?;
+        self.updated = false;
+
+        // The collector may accept the request but drop some of the points
+        let body = response.body_mut().read_to_vec()?;
+        if let Ok(response) = ExportMetricsServiceResponse::decode(&body[..])
+            && let Some(partial) = response.partial_success
+            && partial.rejected_data_points > 0
+        {
+            eprintln!(
+                "OTLP collector rejected {} data points: {}",
+                partial.rejected_data_points, partial.error_message
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Resource attributes of the run, following the OpenTelemetry semantic conventions where
+/// they exist.
+fn resource(run: &RunInfo) -> Resource {
+    let mut attributes = vec![
+        string_attribute("service.name", env!("CARGO_PKG_NAME")),
+        string_attribute("service.version", env!("CARGO_PKG_VERSION")),
+        string_attribute("host.name", &run.host),
+        string_attribute("host.cpu.model.name", &run.hardware.cpu),
+        string_attribute("process.command_line", &run.command),
+        string_attribute("ecocode.run.id", &run.id),
+    ];
+    if !run.args.is_empty() {
+        let args = run
+            .args
+            .iter()
+            .map(|arg| AnyValue {
+                value: Some(any_value::Value::StringValue(arg.clone())),
+            })
+            .collect();
+        attributes.push(attribute(
+            "process.command_args",
+            any_value::Value::ArrayValue(ArrayValue { values: args }),
+        ));
+    }
+    attributes.extend(
+        run.labels
+            .iter()
+            .map(|(key, value)| string_attribute(key, value)),
+    );
+    Resource {
+        attributes,
+        ..Default::default()
+    }
+}
+
+fn attribute(key: &str, value: any_value::Value) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue { value: Some(value) }),
+    }
+}
+
+fn string_attribute(key: &str, value: &str) -> KeyValue {
+    attribute(key, any_value::Value::StringValue(value.to_string()))
+}
+
+/// Parses `key=value` pairs separated by commas.
+fn parse_headers(value: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    value
+        .split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => Ok((key.trim().to_string(), value.trim().to_string())),
+            None => Err(format!("{}: expected key=value, got {}", HEADERS_VAR, pair).into()),
+        })
+        .collect()
+}
+
+impl Exporter for OtlpExporter {
+    fn exporter_type(&self) -> ExporterType {
+        ExporterType::Otlp
+    }
+
+    fn add_record(&mut self, record: Record) -> Result<(), Box<dyn Error>> {
+        let series = self
+            .series
+            .entry((record.scope.as_str(), record.pid))
+            .or_default();
+        series.time_unix_nano = record.timestamp as u64 * 1_000_000;
+        series.utilization.insert("cpu", record.cpu_usage / 100.0);
+        if let Some(usage) = record.gpu_usage {
+            series.utilization.insert("gpu", usage / 100.0);
+        }
+
+        let components = [
+            ("cpu", Some(record.cpu_power_w)),
+            ("gpu", record.gpu_power_w),
+            ("system", record.system_power_w),
+        ];
+        for (component, power_w) in components {
+            if let Some(power_w) = power_w {
+                series.power_w.insert(component, power_w);
+                *series.energy_j.entry(component).or_default() += record.joules(power_w);
+            }
+        }
+        let estimates = [
+            ("wall", record.wall_power_w),
+            ("facility", record.facility_power_w),
+        ];
+        for (kind, power_w) in estimates {
+            if let Some(power_w) = power_w {
+                series.estimated_power_w.insert(kind, power_w);
+                *series.estimated_energy_j.entry(kind).or_default() += record.joules(power_w);
+            }
+        }
+        if let Some(grams) = record.carbon_g {
+            *series.carbon_g.get_or_insert(0.0) += grams;
+        }
+        if let Some(cost) = record.cost {
+            *series.cost.get_or_insert(0.0) += cost;
+        }
+        if let Some(litres) = record.water_l {
+            *series.water_l.get_or_insert(0.0) += litres;
+        }
+        self.updated = true;
+        Ok(())
+    }
+
+    fn export(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        self.summary = Some(summary.clone());
+        self.push()?;
+        eprintln!("\n[OTLP EXPORT]");
+        eprintln!("Metrics pushed to: {}", self.url);
+        Ok(())
+    }
+
+    fn export_line(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn end_interval(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.updated {
+            return Ok(());
+        }
+        // The counters are cumulative, so a failed push loses nothing but gauge values;
+        // a collector that is briefly unreachable must not end the measurement
+        if let Err(e) = self.push() {
+            eprintln!("OTLP push to {} failed, will retry: {}", self.url, e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::{Scope, testing};
+    use crate::summary::EnergyMeter;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn pushes_protobuf_metrics() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let collector = thread::spawn(move || [0, 1].map(|_| testing::accept_request(&listener)));
+
+        let mut exporter = OtlpExporter::new(&endpoint, &testing::run()).unwrap();
+        let mut meter = EnergyMeter::new();
+        let mut record = testing::record(1);
+        record.carbon_g = Some(0.5);
+        record.wall_power_w = Some(12.0);
+        meter.add_record(&record);
+        let mut process = record.clone();
+        process.scope = Scope::Process;
+        process.pid = 43;
+        for record in [record, process] {
+            exporter.add_record(record).unwrap();
+            exporter.export_line().unwrap();
+        }
+        exporter.end_interval().unwrap();
+        exporter.export(&meter.summary(42, 0)).unwrap();
+
+        // One push for the interval, with both series, and one at the end of the run
+        let [(_, interval), (head, body)] = collector.join().unwrap();
+        let request = ExportMetricsServiceRequest::decode(&interval[..]).unwrap();
+        let metrics = &request.resource_metrics[0].scope_metrics[0].metrics;
+        assert_eq!(metrics.len(), 6);
+        let Some(metric::Data::Gauge(power)) = &metrics[0].data else {
+            panic!("ecocode.power is not a gauge");
+        };
+        assert_eq!(power.data_points.len(), 2);
+
+        assert!(head.starts_with("POST /v1/metrics HTTP/1.1\r\n"));
+        assert!(
+            head.to_ascii_lowercase()
+                .contains("content-type: application/x-protobuf")
+        );
+        let request = ExportMetricsServiceRequest::decode(&body[..]).unwrap();
+        let metrics = &request.resource_metrics[0].scope_metrics[0].metrics;
+        let names: Vec<(&str, &str)> = metrics
+            .iter()
+            .map(|m| (m.name.as_str(), m.unit.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("ecocode.power", "W"),
+                ("ecocode.energy", "J"),
+                ("ecocode.utilization", "1"),
+                ("ecocode.estimated_power", "W"),
+                ("ecocode.estimated_energy", "J"),
+                ("ecocode.carbon", "g"),
+                ("ecocode.run.duration", "s"),
+                ("ecocode.run.power", "W"),
+                ("ecocode.run.energy", "J"),
+                ("ecocode.run.estimated_energy", "J"),
+                ("ecocode.run.carbon", "g"),
+            ]
+        );
+        let resource = request.resource_metrics[0].resource.as_ref().unwrap();
+        let attribute = |key: &str| {
+            let value = resource.attributes.iter().find(|a| a.key == key).unwrap();
+            value.value.clone().unwrap().value.unwrap()
+        };
+        assert_eq!(
+            attribute("ecocode.run.id"),
+            any_value::Value::StringValue("1700000000000-42".into())
+        );
+        assert_eq!(
+            attribute("host.name"),
+            any_value::Value::StringValue("lab-1".into())
+        );
+
+        let Some(metric::Data::Gauge(power)) = &metrics[0].data else {
+            panic!("ecocode.power is not a gauge");
+        };
+        assert_eq!(
+            power.data_points[0].value,
+            Some(number_data_point::Value::AsDouble(10.0))
+        );
+        for cumulative in [&metrics[1], &metrics[4], &metrics[5]] {
+            let Some(metric::Data::Sum(sum)) = &cumulative.data else {
+                panic!("{} is not a sum", cumulative.name);
+            };
+            assert_eq!(
+                sum.aggregation_temporality,
+                AggregationTemporality::Cumulative as i32
+            );
+            assert!(sum.is_monotonic);
+        }
+        let Some(metric::Data::Gauge(estimated)) = &metrics[3].data else {
+            panic!("ecocode.estimated_power is not a gauge");
+        };
+        assert_eq!(estimated.data_points[0].attributes[2].key, "kind");
+        let Some(metric::Data::Gauge(utilization)) = &metrics[2].data else {
+            panic!("ecocode.utilization is not a gauge");
+        };
+        assert_eq!(
+            utilization.data_points[0].value,
+            Some(number_data_point::Value::AsDouble(0.5))
+        );
+
+        // The run summary, as gauges without PID and scope
+        let summary: Vec<(&str, Vec<(String, f64)>)> = metrics[6..]
+            .iter()
+            .map(|m| {
+                let Some(metric::Data::Gauge(gauge)) = &m.data else {
+                    panic!("{} is not a gauge", m.name);
+                };
+                let points = gauge
+                    .data_points
+                    .iter()
+                    .map(|p| {
+                        let label = p.attributes.iter().map(|a| a.key.clone()).collect();
+                        let Some(number_data_point::Value::AsDouble(value)) = p.value else {
+                            panic!("{} is not a double", m.name);
+                        };
+                        (label, value)
+                    })
+                    .collect();
+                (m.name.as_str(), points)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("ecocode.run.duration", vec![(String::new(), 1.0)]),
+                (
+                    "ecocode.run.power",
+                    vec![
+                        ("stat".to_string(), 10.0),
+                        ("stat".to_string(), 10.0),
+                        ("stat".to_string(), 10.0),
+                    ]
+                ),
+                ("ecocode.run.energy", vec![("component".to_string(), 10.0)]),
+                (
+                    "ecocode.run.estimated_energy",
+                    vec![("kind".to_string(), 12.0)]
+                ),
+                ("ecocode.run.carbon", vec![(String::new(), 0.5)]),
+            ]
+        );
+    }
+}
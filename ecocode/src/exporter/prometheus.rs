@@ -0,0 +1,554 @@
+//! Prometheus exporter — serves the latest measurements on an HTTP `/metrics` endpoint.
+//!
+//! Power is exposed as gauges and the attributed energy as counters, per measured
+//! component (`cpu`, `gpu`, `system`), labelled with the run ID, the target command and
+//! the PID and scope of the record. The `wall` and `facility` power estimated with the
+//! PSU and PUE options are separate metrics, by `kind`, as they include the measured
+//! components. With `--carbon-intensity`, `--energy-price` and the water
+//! options, the emissions, the cost (labelled with its currency) and the water of the
+//! attributed energy are counters as well.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use tiny_http::{Header, Response, Server};
+
+use crate::exporter::{Exporter, ExporterType, Record, RunInfo};
+use crate::summary::Summary;
+
+/// Default address of the `/metrics` endpoint.
+pub const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:9185";
+
+/// Content type of the Prometheus text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Latest values of one (scope, PID) series.
+#[derive(Default)]
+struct Series {
+    cpu_usage: f64,
+    gpu_usage: Option<f64>,
+    power_w: BTreeMap<&'static str, f64>,
+    energy_j: BTreeMap<&'static str, f64>,
+    /// Estimated wall and facility power and energy, by kind.
+    estimated_power_w: BTreeMap<&'static str, f64>,
+    estimated_energy_j: BTreeMap<&'static str, f64>,
+    carbon_g: Option<f64>,
+    cost: Option<f64>,
+    water_l: Option<f64>,
+}
+
+/// Metric state of a run, rendered in the Prometheus text format.
+///
+/// Shared by every exporter that speaks the Prometheus format, so they expose the same
+/// metrics.
+pub struct Metrics {
+    run: RunInfo,
+    series: BTreeMap<(&'static str, u32), Series>,
+    summary: Option<Summary>,
+}
+
+impl Metrics {
+    pub fn new(run: RunInfo) -> Metrics {
+        Metrics {
+            run,
+            series: BTreeMap::new(),
+            summary: None,
+        }
+    }
+
+    /// Updates the gauges of the record's series and adds its energy to the counters.
+    pub fn update(&mut self, record: &Record) {
+        let series = self
+            .series
+            .entry((record.scope.as_str(), record.pid))
+            .or_default();
+        series.cpu_usage = record.cpu_usage;
+        series.gpu_usage = record.gpu_usage;
+
+        let components = [
+            ("cpu", Some(record.cpu_power_w)),
+            ("gpu", record.gpu_power_w),
+            ("system", record.system_power_w),
+        ];
+        for (component, power_w) in components {
+            if let Some(power_w) = power_w {
+                series.power_w.insert(component, power_w);
+                *series.energy_j.entry(component).or_default() += record.joules(power_w);
+            }
+        }
+        let estimates = [
+            ("wall", record.wall_power_w),
+            ("facility", record.facility_power_w),
+        ];
+        for (kind, power_w) in estimates {
+            if let Some(power_w) = power_w {
+                series.estimated_power_w.insert(kind, power_w);
+                *series.estimated_energy_j.entry(kind).or_default() += record.joules(power_w);
+            }
+        }
+        if let Some(grams) = record.carbon_g {
+            *series.carbon_g.get_or_insert(0.0) += grams;
+        }
+        if let Some(cost) = record.cost {
+            *series.cost.get_or_insert(0.0) += cost;
+        }
+        if let Some(litres) = record.water_l {
+            *series.water_l.get_or_insert(0.0) += litres;
+        }
+    }
+
+    pub fn finish(&mut self, summary: &Summary) {
+        self.summary = Some(summary.clone());
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "ecocode_power_watts",
+            "gauge",
+            "Power attributed to the target during the last sample.",
+        );
+        for (key, series) in &self.series {
+            for (component, value) in &series.power_w {
+                self.sample(
+                    &mut out,
+                    "ecocode_power_watts",
+                    key,
+                    Some(("component", component)),
+                    *value,
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "ecocode_energy_joules_total",
+            "counter",
+            "Energy attributed to the target since the start of the run.",
+        );
+        for (key, series) in &self.series {
+            for (component, value) in &series.energy_j {
+                self.sample(
+                    &mut out,
+                    "ecocode_energy_joules_total",
+                    key,
+                    Some(("component", component)),
+                    *value,
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "ecocode_estimated_power_watts",
+            "gauge",
+            "Estimated wall or facility power of the target during the last sample.",
+        );
+        for (key, series) in &self.series {
+            for (kind, value) in &series.estimated_power_w {
+                self.sample(
+                    &mut out,
+                    "ecocode_estimated_power_watts",
+                    key,
+                    Some(("kind", kind)),
+                    *value,
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "ecocode_estimated_energy_joules_total",
+            "counter",
+            "Estimated wall or facility energy of the target since the start of the run.",
+        );
+        for (key, series) in &self.series {
+            for (kind, value) in &series.estimated_energy_j {
+                self.sample(
+                    &mut out,
+                    "ecocode_estimated_energy_joules_total",
+                    key,
+                    Some(("kind", kind)),
+                    *value,
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "ecocode_carbon_grams_total",
+            "counter",
+            "Emissions of the attributed energy since the start of the run, in gCO2e.",
+        );
+        for (key, series) in &self.series {
+            if let Some(grams) = series.carbon_g {
+                self.sample(&mut out, "ecocode_carbon_grams_total", key, None, grams);
+            }
+        }
+
+        header(
+            &mut out,
+            "ecocode_cost_total",
+            "counter",
+            "Cost of the attributed energy since the start of the run.",
+        );
+        let currency = self.run.currency.as_deref().unwrap_or_default();
+        for (key, series) in &self.series {
+            if let Some(cost) = series.cost {
+                let label = Some(("currency", currency));
+                self.sample(&mut out, "ecocode_cost_total", key, label, cost);
+            }
+        }
+
+        header(
+            &mut out,
+            "ecocode_water_litres_total",
+            "counter",
+            "Water consumed for the attributed energy since the start of the run, in litres.",
+        );
+        for (key, series) in &self.series {
+            if let Some(litres) = series.water_l {
+                self.sample(&mut out, "ecocode_water_litres_total", key, None, litres);
+            }
+        }
+
+        header(
+            &mut out,
+            "ecocode_cpu_usage_percent",
+            "gauge",
+            "Share of the whole CPU used by the target during the last sample.",
+        );
+        for (key, series) in &self.series {
+            self.sample(
+                &mut out,
+                "ecocode_cpu_usage_percent",
+                key,
+                None,
+                series.cpu_usage,
+            );
+        }
+
+        header(
+            &mut out,
+            "ecocode_gpu_usage_percent",
+            "gauge",
+            "Share of the GPU used by the target during the last sample.",
+        );
+        for (key, series) in &self.series {
+            if let Some(usage) = series.gpu_usage {
+                self.sample(&mut out, "ecocode_gpu_usage_percent", key, None, usage);
+            }
+        }
+
+        if let Some(summary) = &self.summary {
+            let labels = self.run_labels();
+            header(
+                &mut out,
+                "ecocode_run_duration_seconds",
+                "gauge",
+                "Duration of the finished run.",
+            );
+            let _ = writeln!(
+                out,
+                "ecocode_run_duration_seconds{{{}}} {}",
+                labels, summary.duration_secs
+            );
+            header(
+                &mut out,
+                "ecocode_run_power_watts",
+                "gauge",
+                "Mean, peak and p95 total power of the finished run.",
+            );
+            for (stat, value) in [
+                ("mean", summary.mean_power_w),
+                ("peak", summary.peak_power_w),
+                ("p95", summary.p95_power_w),
+            ] {
+                let _ = writeln!(
+                    out,
+                    "ecocode_run_power_watts{{{},stat=\"{}\"}} {}",
+                    labels, stat, value
+                );
+            }
+        }
+        out
+    }
+
+    fn run_labels(&self) -> String {
+        format!(
+            "run_id=\"{}\",command=\"{}\"",
+            escape(&self.run.id),
+            escape(&self.run.command)
+        )
+    }
+
+    fn sample(
+        &self,
+        out: &mut String,
+        name: &str,
+        (scope, pid): &(&'static str, u32),
+        label: Option<(&str, &str)>,
+        value: f64,
+    ) {
+        let mut labels = format!("{},pid=\"{}\",scope=\"{}\"", self.run_labels(), pid, scope);
+        if let Some((name, value)) = label {
+            let _ = write!(labels, ",{}=\"{}\"", name, escape(value));
+        }
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes a label value as required by the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Last rendered metrics, shared with the server thread.
+#[derive(Default)]
+struct Page {
+    body: String,
+    /// The body includes the run summary.
+    finished: bool,
+    /// The summary has been scraped at least once.
+    summary_scraped: bool,
+}
+
+/// Serves the metrics of the run on `http://<addr>/metrics` from a background thread.
+///
+/// The run summary is only known at the end, so the endpoint stays up after the run
+/// until it has been scraped once, for at most the linger time.
+pub struct PrometheusExporter {
+    metrics: Metrics,
+    page: Arc<(Mutex<Page>, Condvar)>,
+    server: Arc<Server>,
+    addr: String,
+    linger: Duration,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PrometheusExporter {
+    /// Starts listening on `addr`; port 0 picks a free port.
+    pub fn new(
+        addr: &str,
+        run: RunInfo,
+        linger: Duration,
+    ) -> Result<PrometheusExporter, Box<dyn Error>> {
+        let server = Server::http(addr).map_err(|e| format!("cannot listen on {}: {}", addr, e))?;
+        let server = Arc::new(server);
+        let page = Arc::new((Mutex::new(Page::default()), Condvar::new()));
+        let addr = server.server_addr().to_string();
+        eprintln!("Prometheus metrics on http://{}/metrics", addr);
+
+        let handle = {
+            let server = Arc::clone(&server);
+            let page = Arc::clone(&page);
+            thread::spawn(move || serve(&server, &page))
+        };
+        Ok(PrometheusExporter {
+            metrics: Metrics::new(run),
+            page,
+            server,
+            addr,
+            linger,
+            handle: Some(handle),
+        })
+    }
+
+    fn publish(&mut self) {
+        let rendered = self.metrics.render();
+        let mut page = self.page.0.lock().unwrap();
+        page.body = rendered;
+        page.finished = self.metrics.summary.is_some();
+    }
+}
+
+/// Answers scrapes until the server is unblocked.
+fn serve(server: &Server, (page, scraped): &(Mutex<Page>, Condvar)) {
+    for request in server.incoming_requests() {
+        let result = if request.url() == "/metrics" {
+            let body = {
+                let mut page = page.lock().unwrap();
+                if page.finished {
+                    page.summary_scraped = true;
+                    scraped.notify_all();
+                }
+                page.body.clone()
+            };
+            let header = Header::from_bytes("Content-Type", CONTENT_TYPE).unwrap();
+            request.respond(Response::from_string(body).with_header(header))
+        } else {
+            request.respond(Response::from_string("Not Found\n").with_status_code(404))
+        };
+        if let Err(e) = result {
+            eprintln!("Error answering a metrics scrape: {}", e);
+        }
+    }
+}
+
+impl Exporter for PrometheusExporter {
+    fn exporter_type(&self) -> ExporterType {
+        ExporterType::Prometheus
+    }
+
+    fn add_record(&mut self, record: Record) -> Result<(), Box<dyn Error>> {
+        self.metrics.update(&record);
+        Ok(())
+    }
+
+    fn export(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        self.metrics.finish(summary);
+        self.publish();
+        eprintln!("\n[PROMETHEUS EXPORT]");
+        eprintln!("Metrics served on http://{}/metrics", self.addr);
+        if !self.linger.is_zero() {
+            eprintln!(
+                "Waiting up to {} s for a scrape of the summary",
+                self.linger.as_secs_f64()
+            );
+        }
+        Ok(())
+    }
+
+    fn export_line(&mut self) -> Result<(), Box<dyn Error>> {
+        self.publish();
+        Ok(())
+    }
+}
+
+impl Drop for PrometheusExporter {
+    fn drop(&mut self) {
+        // Keep serving until the summary has been scraped, or the linger time is over
+        let (page, scraped) = &*self.page;
+        let page = page.lock().unwrap();
+        if page.finished {
+            let _ = scraped.wait_timeout_while(page, self.linger, |p| !p.summary_scraped);
+        } else {
+            drop(page);
+        }
+        self.server.unblock();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::testing;
+    use crate::summary::EnergyMeter;
+    use std::io::{Read, Write as _};
+    use std::net::TcpStream;
+    use std::time::Instant;
+
+    /// Fetches `path` and returns the status code and the body.
+    fn scrape(addr: &str, path: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, addr).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, body.to_string())
+    }
+
+    #[test]
+    fn estimates_are_separate_from_measured_components() {
+        let mut metrics = Metrics::new(testing::run());
+        let mut record = testing::record(1);
+        record.wall_power_w = Some(12.0);
+        record.facility_power_w = Some(18.0);
+        metrics.update(&record);
+
+        let out = metrics.render();
+        let labels = "run_id=\"1700000000000-42\",command=\"make test\",pid=\"42\",scope=\"total\"";
+        let components: Vec<&str> = out
+            .lines()
+            .filter(|l| l.starts_with("ecocode_energy_joules_total{"))
+            .collect();
+        assert_eq!(
+            components,
+            [format!(
+                "ecocode_energy_joules_total{{{},component=\"cpu\"}} 10",
+                labels
+            )]
+        );
+        for line in [
+            format!(
+                "ecocode_estimated_power_watts{{{},kind=\"facility\"}} 18",
+                labels
+            ),
+            format!(
+                "ecocode_estimated_power_watts{{{},kind=\"wall\"}} 12",
+                labels
+            ),
+            format!(
+                "ecocode_estimated_energy_joules_total{{{},kind=\"wall\"}} 12",
+                labels
+            ),
+        ] {
+            assert!(out.lines().any(|l| l == line), "{} missing", line);
+        }
+    }
+
+    #[test]
+    fn serves_records_and_summary() {
+        let mut exporter =
+            PrometheusExporter::new("127.0.0.1:0", testing::run(), Duration::from_secs(10))
+                .unwrap();
+        let addr = exporter.addr.clone();
+        let mut meter = EnergyMeter::new();
+        for id in 1..=2 {
+            let record = testing::record(id);
+            meter.add_record(&record);
+            exporter.add_record(record).unwrap();
+            exporter.export_line().unwrap();
+        }
+
+        let (status, response) = scrape(&addr, "/metrics");
+        assert_eq!(status, 200);
+        assert!(response.contains("# TYPE ecocode_energy_joules_total counter"));
+        assert!(response.contains(
+            "ecocode_energy_joules_total{run_id=\"1700000000000-42\",command=\"make test\",\
+             pid=\"42\",scope=\"total\",component=\"cpu\"} 20"
+        ));
+        assert!(!response.contains("ecocode_run_duration_seconds{"));
+        assert_eq!(scrape(&addr, "/other").0, 404);
+
+        exporter.export(&meter.summary(42, 0)).unwrap();
+        let (_, response) = scrape(&addr, "/metrics");
+        assert!(response.contains("ecocode_run_duration_seconds{"));
+
+        // The summary was scraped, so the endpoint closes without lingering
+        let start = Instant::now();
+        drop(exporter);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn lingers_until_the_linger_time_without_a_scrape() {
+        let linger = Duration::from_millis(200);
+        let mut exporter = PrometheusExporter::new("127.0.0.1:0", testing::run(), linger).unwrap();
+        exporter.add_record(testing::record(1)).unwrap();
+        exporter.export(&EnergyMeter::new().summary(42, 0)).unwrap();
+
+        let start = Instant::now();
+        drop(exporter);
+        assert!(start.elapsed() >= linger);
+    }
+}
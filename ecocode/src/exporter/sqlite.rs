@@ -1,63 +1,419 @@
 //! SQLite exporter — persists measurement records into a SQLite database.
+//!
+//! A database can hold any number of runs: each run gets a row in `runs` with its
+//! metadata and end-of-run summary, and its records go to `samples`, keyed by run ID.
+//! The schema version is kept in `PRAGMA user_version` and older databases are
+//! migrated when opened.
+//!
+//! The database runs in WAL mode and samples are written in batches: they are buffered
+//! and inserted in a single transaction once [`BATCH_SIZE`] records are pending or
+//! [`FLUSH_INTERVAL`] has passed, which saves an fsync per sample.
 
+use std::time::{Duration, Instant, SystemTime};
 
-use rusqlite::Connection;
+use rusqlite::{Connection, Transaction, params};
 
-use crate::exporter::{Exporter, ExporterType};
+use crate::exporter::{Exporter, ExporterType, Record, RunInfo};
+use crate::summary::Summary;
 
-/// Exports records to a SQLite database file.
+/// Schema migrations; the database's `user_version` is the number of applied entries.
 ///
-/// The `records` table is created automatically if it does not exist.
+/// Tables from before versioning (`records`, `summaries`) are left untouched.
+const MIGRATIONS: &[&str] = &[
+    // 1: runs and their samples
+    "CREATE TABLE runs (
+        id TEXT PRIMARY KEY,
+        command TEXT,
+        args TEXT,
+        started_at INTEGER,
+        ended_at INTEGER,
+        exit_code INTEGER,
+        host TEXT,
+        hardware TEXT,
+        labels TEXT,
+        duration_s REAL,
+        samples INTEGER,
+        cpu_energy_j REAL,
+        gpu_energy_j REAL,
+        system_energy_j REAL,
+        total_energy_j REAL,
+        mean_power_w REAL,
+        peak_power_w REAL,
+        p95_power_w REAL
+    );
+    CREATE TABLE samples (
+        id INTEGER PRIMARY KEY,
+        run_id TEXT NOT NULL REFERENCES runs(id),
+        sample INTEGER,
+        pid INTEGER,
+        scope TEXT,
+        timestamp INTEGER,
+        interval_s REAL,
+        cpu_usage REAL,
+        cpu_energy REAL,
+        cpu_domains TEXT,
+        gpu_usage REAL,
+        gpu_energy REAL,
+        system_energy REAL,
+        counter_wrapped INTEGER
+    );
+    CREATE INDEX samples_run_timestamp ON samples (run_id, timestamp);",
+    // 2: parent PID and name of per-process samples
+    "ALTER TABLE samples ADD COLUMN ppid INTEGER;
+    ALTER TABLE samples ADD COLUMN name TEXT;",
+    // 3: carbon emissions
+    "ALTER TABLE samples ADD COLUMN carbon_g REAL;
+    ALTER TABLE runs ADD COLUMN carbon_g REAL;
+    ALTER TABLE runs ADD COLUMN carbon_intensity_g_per_kwh REAL;",
+    // 4: energy cost
+    "ALTER TABLE samples ADD COLUMN cost REAL;
+    ALTER TABLE runs ADD COLUMN cost REAL;
+    ALTER TABLE runs ADD COLUMN cost_currency TEXT;
+    ALTER TABLE runs ADD COLUMN cost_price_per_kwh REAL;",
+    // 5: estimated wall and facility power
+    "ALTER TABLE samples ADD COLUMN wall_power_w REAL;
+    ALTER TABLE samples ADD COLUMN facility_power_w REAL;
+    ALTER TABLE runs ADD COLUMN wall_energy_j REAL;
+    ALTER TABLE runs ADD COLUMN facility_energy_j REAL;",
+    // 6: water footprint
+    "ALTER TABLE samples ADD COLUMN water_l REAL;
+    ALTER TABLE runs ADD COLUMN water_l REAL;
+    ALTER TABLE runs ADD COLUMN water_l_per_kwh REAL;",
+    // 7: sample columns named after their unit, they always held watts
+    "ALTER TABLE samples RENAME COLUMN cpu_energy TO cpu_power_w;
+    ALTER TABLE samples RENAME COLUMN gpu_energy TO gpu_power_w;
+    ALTER TABLE samples RENAME COLUMN system_energy TO system_power_w;",
+    // 8: why the measurement failed
+    "ALTER TABLE runs ADD COLUMN error TEXT;",
+];
+
+/// Number of pending records that triggers a flush.
+pub const BATCH_SIZE: usize = 256;
+
+/// Longest time records stay pending before they are flushed.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+const INSERT_SAMPLE: &str = "INSERT INTO samples (run_id, sample, pid, scope, ppid, name, timestamp, interval_s, cpu_usage, cpu_power_w, cpu_domains, gpu_usage, gpu_power_w, system_power_w, wall_power_w, facility_power_w, carbon_g, cost, water_l, counter_wrapped) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
+/// Exports records to a SQLite database file.
 pub struct SqliteExporter {
     db: Connection,
+    run_id: String,
+    /// Records not yet written to the database.
+    pending: Vec<Record>,
+    last_flush: Instant,
 }
 
 impl SqliteExporter {
-    pub fn new(db_path: String) -> SqliteExporter {
-        SqliteExporter {
-            db: Connection::open(db_path).unwrap(), //open or create a db
+    /// Opens or creates the database, migrates it and registers the run.
+    pub fn new(
+        db_path: String,
+        run: RunInfo,
+    ) -> Result<SqliteExporter, Box<dyn std::error::Error>> {
+        let mut db = Connection::open(db_path)?; //open or create a db
+        // WAL makes commits cheap and lets readers query the database during the run
+        db.pragma_update(None, "journal_mode", "WAL")?;
+        db.pragma_update(None, "synchronous", "NORMAL")?;
+        migrate(&mut db)?;
+        db.execute(
+            "INSERT INTO runs (id, command, args, started_at, host, hardware, labels) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            (
+                &run.id,
+                &run.command,
+                serde_json::to_string(&run.args)?,
+                run.started_at,
+                &run.host,
+                serde_json::to_string(&run.hardware)?,
+                serde_json::to_string(&run.labels)?,
+            ),
+        )?;
+        Ok(SqliteExporter {
+            db,
+            run_id: run.id,
+            pending: Vec::with_capacity(BATCH_SIZE),
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Writes the pending records in one transaction. On failure they stay pending and
+    /// are retried with the next flush.
+    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let tx = self.db.transaction()?;
+        {
+            let mut insert = tx.prepare_cached(INSERT_SAMPLE)?;
+            for record in &self.pending {
+                insert.execute(params![
+                    &self.run_id,
+                    record.id,
+                    record.pid,
+                    record.scope.as_str(),
+                    record.ppid,
+                    record.name.as_deref(),
+                    record.timestamp,
+                    record.interval_secs,
+                    record.cpu_usage,
+                    record.cpu_power_w,
+                    serde_json::to_string(&record.cpu_domains)?,
+                    record.gpu_usage,
+                    record.gpu_power_w,
+                    record.system_power_w,
+                    record.wall_power_w,
+                    record.facility_power_w,
+                    record.carbon_g,
+                    record.cost,
+                    record.water_l,
+                    record.counter_wrapped,
+                ])?;
+            }
         }
+        tx.commit()?;
+        self.pending.clear();
+        self.last_flush = Instant::now();
+        Ok(())
     }
 }
 
+/// Applies the migrations the database has not seen yet, all in one transaction.
+fn migrate(db: &mut Connection) -> Result<(), Box<dyn std::error::Error>> {
+    let version: i64 = db.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let latest = MIGRATIONS.len() as i64;
+    if version > latest {
+        return Err(format!(
+            "database schema version {} is newer than this EcoCode supports ({})",
+            version, latest
+        )
+        .into());
+    }
+    let tx: Transaction = db.transaction()?;
+    for migration in &MIGRATIONS[version as usize..] {
+        tx.execute_batch(migration)?;
+    }
+    tx.pragma_update(None, "user_version", latest)?;
+    tx.commit()?;
+    Ok(())
+}
+
 impl Exporter for SqliteExporter {
     fn exporter_type(&self) -> ExporterType {
         ExporterType::Sqlite
     }
 
-    fn add_record(&mut self, record: super::Record) -> Result<(), Box<dyn std::error::Error>> {
-        let sql = "CREATE TABLE IF NOT EXISTS records (
-            id INTEGER PRIMARY KEY,
-            pid INTEGER,
-            timestamp INTEGER,
-            cpu_usage REAL,
-            cpu_energy REAL,
-            gpu_usage REAL,
-            gpu_energy REAL
-        )";
-
-        self.db.execute(sql, ())?;
+    fn add_record(&mut self, record: Record) -> Result<(), Box<dyn std::error::Error>> {
+        self.pending.push(record);
+        if self.pending.len() >= BATCH_SIZE || self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
 
-        let sql = "INSERT INTO records (pid, timestamp, cpu_usage, cpu_energy, gpu_usage, gpu_energy) VALUES (?, ?, ?, ?, ?, ?)";
+    fn export(&mut self, summary: &Summary) -> Result<(), Box<dyn std::error::Error>> {
+        self.flush()?;
+        let ended_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_millis() as i64;
+        let sql = "UPDATE runs SET ended_at = ?, exit_code = ?, error = ?, duration_s = ?, samples = ?, cpu_energy_j = ?, gpu_energy_j = ?, system_energy_j = ?, total_energy_j = ?, wall_energy_j = ?, facility_energy_j = ?, mean_power_w = ?, peak_power_w = ?, p95_power_w = ?, carbon_g = ?, carbon_intensity_g_per_kwh = ?, cost = ?, cost_currency = ?, cost_price_per_kwh = ?, water_l = ?, water_l_per_kwh = ? WHERE id = ?";
         self.db.execute(
             sql,
-            (
-                record.pid,
-                record.timestamp,
-                record.cpu_usage,
-                record.cpu_energy,
-                record.gpu_usage,
-                record.gpu_energy,
-            ),
+            params![
+                ended_at,
+                summary.exit_code,
+                summary.error,
+                summary.duration_secs,
+                summary.samples as i64,
+                summary.cpu_energy.joules,
+                summary.gpu_energy.map(|e| e.joules),
+                summary.system_energy.map(|e| e.joules),
+                summary.total_energy.joules,
+                summary.wall_energy.map(|e| e.joules),
+                summary.facility_energy.map(|e| e.joules),
+                summary.mean_power_w,
+                summary.peak_power_w,
+                summary.p95_power_w,
+                summary.carbon.map(|c| c.grams),
+                summary.carbon.map(|c| c.intensity_g_per_kwh),
+                summary.cost.as_ref().map(|c| c.amount),
+                summary.cost.as_ref().map(|c| c.currency.as_str()),
+                summary.cost.as_ref().map(|c| c.price_per_kwh),
+                summary.water.map(|w| w.litres),
+                summary.water.map(|w| w.l_per_kwh),
+                &self.run_id,
+            ],
         )?;
 
-        Ok(())
-    }
-
-    fn export(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        eprintln!("\n[SQLITE EXPORT]");
+        eprintln!("Run {} stored in the database", self.run_id);
         Ok(())
     }
     fn export_line(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::testing;
+    use crate::summary::EnergyMeter;
+    use std::path::Path;
+
+    fn user_version(db: &Connection) -> i64 {
+        db.pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    /// Samples of the test run committed to the database at `path`, as seen by another
+    /// connection.
+    fn committed_samples(path: &Path) -> i64 {
+        Connection::open(path)
+            .unwrap()
+            .query_row(
+                "SELECT count(*) FROM samples WHERE run_id = ?",
+                [testing::run().id],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    fn exporter(path: &Path) -> SqliteExporter {
+        SqliteExporter::new(path.to_string_lossy().into_owned(), testing::run()).unwrap()
+    }
+
+    #[test]
+    fn creates_the_latest_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("runs.db");
+        drop(exporter(&path));
+
+        let db = Connection::open(&path).unwrap();
+        assert_eq!(user_version(&db), MIGRATIONS.len() as i64);
+        let index: String = db
+            .query_row(
+                "SELECT sql FROM sqlite_master WHERE type = 'index' AND name = 'samples_run_timestamp'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(index.contains("samples (run_id, timestamp)"));
+        let plan: String = db
+            .query_row(
+                "EXPLAIN QUERY PLAN SELECT * FROM samples WHERE run_id = 'a' ORDER BY timestamp",
+                [],
+                |row| row.get(3),
+            )
+            .unwrap();
+        assert!(plan.contains("samples_run_timestamp"), "{}", plan);
+    }
+
+    #[test]
+    fn upgrades_a_version_1_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("runs.db");
+        {
+            let db = Connection::open(&path).unwrap();
+            db.execute_batch(MIGRATIONS[0]).unwrap();
+            db.pragma_update(None, "user_version", 1).unwrap();
+            db.execute_batch(
+                "INSERT INTO runs (id, command, exit_code) VALUES ('old', 'make', 0);
+                INSERT INTO samples (run_id, sample, cpu_energy) VALUES ('old', 1, 5.0);",
+            )
+            .unwrap();
+        }
+        let mut exporter = exporter(&path);
+        exporter.add_record(testing::record(1)).unwrap();
+        exporter.export(&EnergyMeter::new().summary(42, 0)).unwrap();
+
+        let db = Connection::open(&path).unwrap();
+        assert_eq!(user_version(&db), MIGRATIONS.len() as i64);
+        let (command, water): (String, Option<f64>) = db
+            .query_row(
+                "SELECT command, water_l FROM runs WHERE id = 'old'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((command.as_str(), water), ("make", None));
+        let samples: i64 = db
+            .query_row("SELECT count(*) FROM samples", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(samples, 2);
+        // Migration 7 renamed the power columns
+        let power: f64 = db
+            .query_row(
+                "SELECT cpu_power_w FROM samples WHERE run_id = 'old'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(power, 5.0);
+    }
+
+    #[test]
+    fn rejects_a_newer_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("runs.db");
+        Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, "user_version", 99)
+            .unwrap();
+        let error = SqliteExporter::new(path.to_string_lossy().into_owned(), testing::run())
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "database schema version 99 is newer than this EcoCode supports ({})",
+                MIGRATIONS.len()
+            )
+        );
+    }
+
+    #[test]
+    fn export_commits_the_last_partial_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("runs.db");
+        let mut exporter = exporter(&path);
+        let mut meter = EnergyMeter::new();
+        for id in 1..=3 {
+            let record = testing::record(id);
+            meter.add_record(&record);
+            exporter.add_record(record).unwrap();
+        }
+        assert_eq!(committed_samples(&path), 0);
+        exporter.export(&meter.summary(42, 0)).unwrap();
+        assert_eq!(committed_samples(&path), 3);
+
+        let (exit_code, samples, energy): (Option<i32>, i64, f64) = Connection::open(&path)
+            .unwrap()
+            .query_row(
+                "SELECT exit_code, samples, cpu_energy_j FROM runs WHERE id = ?",
+                [testing::run().id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((exit_code, samples, energy), (Some(0), 3, 30.0));
+    }
+
+    #[test]
+    fn a_full_batch_or_the_flush_interval_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("runs.db");
+        let mut exporter = exporter(&path);
+        for id in 1..BATCH_SIZE as u32 {
+            exporter.add_record(testing::record(id)).unwrap();
+        }
+        assert_eq!(committed_samples(&path), 0);
+        exporter
+            .add_record(testing::record(BATCH_SIZE as u32))
+            .unwrap();
+        assert_eq!(committed_samples(&path), BATCH_SIZE as i64);
+
+        exporter.add_record(testing::record(1000)).unwrap();
+        assert_eq!(committed_samples(&path), BATCH_SIZE as i64);
+        exporter.last_flush -= FLUSH_INTERVAL;
+        exporter.add_record(testing::record(1001)).unwrap();
+        assert_eq!(committed_samples(&path), BATCH_SIZE as i64 + 2);
+    }
+}
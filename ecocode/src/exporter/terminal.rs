@@ -1,4 +1,21 @@
-use crate::exporter::{Exporter, ExporterType, Record};
+use crate::exporter::{Exporter, ExporterType, Record, Scope};
+use crate::summary::Summary;
+
+/// Formats an optional measurement, showing `N/A` when the sensor is absent.
+fn fmt_opt(value: Option<f64>, precision: usize) -> String {
+    match value {
+        Some(v) => format!("{:.*}", precision, v),
+        None => "N/A".to_string(),
+    }
+}
+
+/// Per-process rows are drawn as branches below their sample's total row.
+fn row_id(record: &Record) -> String {
+    match record.scope {
+        Scope::Total => record.id.to_string(),
+        Scope::Process => "  └".to_string(),
+    }
+}
 
 pub struct TerminalExporter {
     records: Vec<Record>,
@@ -23,28 +40,66 @@ impl Exporter for TerminalExporter {
         Ok(())
     }
 
-    fn export(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        println!("\n[TERMINAL EXPORT]");
-        println!("\n{}", "=".repeat(80));
+    fn export(&mut self, summary: &Summary) -> Result<(), Box<dyn std::error::Error>> {
+        println!("\n[SUMMARY]");
+        println!("{}", "=".repeat(44));
         println!(
-            "{:<5} {:<8} {:<12} {:<10} {:<10} {:<12} {:<12}",
-            "ID", "PID", "Timestamp", "CPU%", "CPU(W)", "GPU%", "GPU(W)"
+            "{:<12} {:>15} {:>15}",
+            "Component", "Energy(J)", "Energy(Wh)"
         );
-        println!("{}", "-".repeat(80));
-
-        for record in &self.records {
+        println!("{}", "-".repeat(44));
+        let components = [
+            ("CPU", Some(summary.cpu_energy)),
+            ("GPU", summary.gpu_energy),
+            ("SYS", summary.system_energy),
+            ("Total", Some(summary.total_energy)),
+        ];
+        for (name, energy) in components {
+            if let Some(energy) = energy {
+                println!("{:<12} {:>15.3} {:>15.6}", name, energy.joules, energy.wh);
+            }
+        }
+        // Estimates built on the measured total, kept apart from the measurements
+        if summary.wall_energy.is_some() {
+            println!("{}", "-".repeat(44));
+        }
+        let estimates = [
+            ("Wall (est.)", summary.wall_energy),
+            ("Facility", summary.facility_energy),
+        ];
+        for (name, energy) in estimates {
+            if let Some(energy) = energy {
+                println!("{:<12} {:>15.3} {:>15.6}", name, energy.joules, energy.wh);
+            }
+        }
+        println!("{}", "-".repeat(44));
+        println!(
+            "Duration: {:.2} s over {} samples",
+            summary.duration_secs, summary.samples
+        );
+        println!(
+            "Power (W): mean {:.3}, peak {:.3}, p95 {:.3}",
+            summary.mean_power_w, summary.peak_power_w, summary.p95_power_w
+        );
+        if let Some(carbon) = summary.carbon {
+            println!(
+                "CO2e: {:.6} g at {:.1} gCO2e/kWh",
+                carbon.grams, carbon.intensity_g_per_kwh
+            );
+        }
+        if let Some(cost) = &summary.cost {
             println!(
-                "{:<5} {:<8} {:<12} {:<10.2} {:<10.2} {:<12.3} {:<12.3}",
-                record.id,
-                record.pid,
-                record.timestamp,
-                record.cpu_usage,
-                record.cpu_energy,
-                record.gpu_usage,
-                record.gpu_energy
+                "Cost: {:.6} {} at {:.4} {}/kWh",
+                cost.amount, cost.currency, cost.price_per_kwh, cost.currency
             );
         }
-        println!("{}\n", "=".repeat(80));
+        if let Some(water) = summary.water {
+            println!(
+                "Water: {:.6} L at {:.2} L/kWh",
+                water.litres, water.l_per_kwh
+            );
+        }
+        println!("{}\n", "=".repeat(44));
 
         Ok(())
     }
@@ -54,19 +109,29 @@ impl Exporter for TerminalExporter {
         let r = self.records.last().unwrap();
 
         if self.first_record {
-            println!("\n{}", "=".repeat(80));
+            println!("\n{}", "=".repeat(93));
             println!(
-                "{:<5} {:<8} {:<12} {:<10} {:<10} {:<12} {:<12}",
-                "ID", "PID", "Timestamp", "CPU%", "CPU(W)", "GPU%", "GPU(W)"
+                "{:<5} {:<8} {:<12} {:<10} {:<10} {:<12} {:<12} {:<12}",
+                "ID", "PID", "Timestamp", "CPU%", "CPU(W)", "GPU%", "GPU(W)", "SYS(W)"
             );
-            println!("{}", "-".repeat(80));
+            println!("{}", "-".repeat(93));
             self.first_record = false;
         }
 
         println!(
-            "{:<5} {:<8} {:<12} {:<10.2} {:<10.2} {:<12.3} {:<12.3}",
-            r.id, r.pid, r.timestamp, r.cpu_usage, r.cpu_energy, r.gpu_usage, r.gpu_energy
+            "{:<5} {:<8} {:<12} {:<10.2} {:<10.2} {:<12} {:<12} {:<12}",
+            row_id(r),
+            r.pid,
+            r.timestamp,
+            r.cpu_usage,
+            r.cpu_power_w,
+            fmt_opt(r.gpu_usage, 3),
+            fmt_opt(r.gpu_power_w, 3),
+            fmt_opt(r.system_power_w, 3)
         );
+        if r.counter_wrapped {
+            println!("      ^ energy counter wrapped or reset during this sample");
+        }
 
         Ok(())
     }
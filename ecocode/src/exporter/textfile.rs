@@ -0,0 +1,78 @@
+//! Textfile exporter — writes the Prometheus metrics to a `.prom` file for the
+//! node_exporter textfile collector, so no extra port has to be opened.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::exporter::prometheus::Metrics;
+use crate::exporter::{Exporter, ExporterType, Record, RunInfo};
+use crate::summary::Summary;
+
+/// File name used when the destination is a directory.
+const DEFAULT_FILE_NAME: &str = "ecocode.prom";
+
+/// Rewrites a `.prom` file with the same metrics as the HTTP endpoint on every tick.
+pub struct TextfileExporter {
+    path: PathBuf,
+    metrics: Metrics,
+}
+
+impl TextfileExporter {
+    /// `destination` is either the `.prom` file itself or the collector directory.
+    pub fn new(destination: String, run: RunInfo) -> Result<TextfileExporter, Box<dyn Error>> {
+        let mut path = PathBuf::from(destination);
+        if path.is_dir() {
+            path.push(DEFAULT_FILE_NAME);
+        }
+        if path.extension().is_none_or(|ext| ext != "prom") {
+            return Err(format!(
+                "{}: the textfile collector only reads *.prom files",
+                path.display()
+            )
+            .into());
+        }
+        Ok(TextfileExporter {
+            path,
+            metrics: Metrics::new(run),
+        })
+    }
+
+    fn write(&self) -> Result<(), Box<dyn Error>> {
+        write_atomically(&self.path, &self.metrics.render())?;
+        Ok(())
+    }
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it over `path`, so
+/// the collector never reads a partially written file.
+fn write_atomically(path: &Path, contents: &str) -> std::io::Result<()> {
+    // Hidden and without the .prom extension, so the collector ignores it
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.tmp", file_name));
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+impl Exporter for TextfileExporter {
+    fn exporter_type(&self) -> ExporterType {
+        ExporterType::Textfile
+    }
+
+    fn add_record(&mut self, record: Record) -> Result<(), Box<dyn Error>> {
+        self.metrics.update(&record);
+        Ok(())
+    }
+
+    fn export(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        self.metrics.finish(summary);
+        self.write()?;
+        eprintln!("\n[TEXTFILE EXPORT]");
+        eprintln!("Metrics found in File: {}", self.path.display());
+        Ok(())
+    }
+
+    fn export_line(&mut self) -> Result<(), Box<dyn Error>> {
+        self.write()
+    }
+}
@@ -0,0 +1,406 @@
+//! Dashboard state, built from the record stream, and keyboard handling.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::widgets::ListState;
+
+use super::Event;
+use crate::exporter::{Record, RunInfo, Scope};
+use crate::footprint::JOULES_PER_KWH;
+use crate::summary::Summary;
+
+/// Captured output lines kept for the log panel.
+const LOG_LINES: usize = 1000;
+
+/// One sample of the target as a whole.
+pub struct Point {
+    pub cpu_w: f64,
+    pub gpu_w: Option<f64>,
+    pub cpu_usage: f64,
+    pub gpu_usage: Option<f64>,
+    /// Energy attributed since the start of the run, up to and including this sample.
+    pub cpu_j: f64,
+    pub gpu_j: f64,
+    /// Estimated wall and facility energy since the start of the run, None without the
+    /// PSU and PUE options.
+    pub wall_j: Option<f64>,
+    pub facility_j: Option<f64>,
+    /// Emissions since the start of the run, None without a carbon intensity.
+    pub carbon_g: Option<f64>,
+    /// Cost since the start of the run, None without an energy price.
+    pub cost: Option<f64>,
+    /// Water since the start of the run in litres, None without the water options.
+    pub water_l: Option<f64>,
+    pub elapsed_secs: f64,
+}
+
+/// Latest state of one process of the tree.
+pub struct Process {
+    pub ppid: Option<u32>,
+    pub name: String,
+    pub cpu_usage: f64,
+    pub power_w: f64,
+    /// Energy attributed to the process since it was first seen.
+    pub energy_j: f64,
+    /// ID of the last sample the process appeared in.
+    last_seen: u32,
+}
+
+/// A visible line of the process tree.
+pub struct TreeRow {
+    pub pid: u32,
+    pub depth: usize,
+    pub has_children: bool,
+    pub collapsed: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Running,
+    /// A stop was requested from the dashboard.
+    Stopping,
+    Finished,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Focus {
+    Processes,
+    Log,
+}
+
+/// What the dashboard loop has to do after a key.
+pub enum Action {
+    None,
+    /// Stop the run.
+    Stop,
+    /// Close the dashboard.
+    Quit,
+}
+
+pub struct App {
+    pub run: RunInfo,
+    pub status: Status,
+    pub summary: Option<Summary>,
+    /// Every sample of the target, oldest first.
+    pub history: Vec<Point>,
+    pub processes: BTreeMap<u32, Process>,
+    /// ID of the latest per-process sample.
+    latest_process_sample: u32,
+    pub log: VecDeque<String>,
+    /// Events that arrived while paused, applied on resume.
+    held: Vec<Event>,
+    pub paused: bool,
+    /// How many samples back from the newest one the view ends, while paused.
+    pub offset: usize,
+    pub focus: Focus,
+    collapsed: HashSet<u32>,
+    pub tree_state: ListState,
+    /// Lines scrolled up from the bottom of the log.
+    pub log_scroll: usize,
+}
+
+impl App {
+    pub fn new(run: RunInfo) -> App {
+        App {
+            run,
+            status: Status::Running,
+            summary: None,
+            history: Vec::new(),
+            processes: BTreeMap::new(),
+            latest_process_sample: 0,
+            log: VecDeque::new(),
+            held: Vec::new(),
+            paused: false,
+            offset: 0,
+            focus: Focus::Processes,
+            collapsed: HashSet::new(),
+            tree_state: ListState::default().with_selected(Some(0)),
+            log_scroll: 0,
+        }
+    }
+
+    pub fn handle(&mut self, event: Event) {
+        // Output is still shown while paused, measurements are held back
+        match event {
+            Event::Log(line) => {
+                self.log.push_back(line);
+                if self.log.len() > LOG_LINES {
+                    self.log.pop_front();
+                }
+            }
+            event if self.paused => self.held.push(event),
+            Event::Record(record) => self.add_record(&record),
+            Event::Finished(summary) => {
+                self.summary = Some(*summary);
+                self.status = Status::Finished;
+            }
+            Event::Ended => self.status = Status::Finished,
+        }
+    }
+
+    fn add_record(&mut self, record: &Record) {
+        match record.scope {
+            Scope::Total => {
+                let last = self.history.last();
+                self.history.push(Point {
+                    cpu_w: record.cpu_power_w,
+                    gpu_w: record.gpu_power_w,
+                    cpu_usage: record.cpu_usage,
+                    gpu_usage: record.gpu_usage,
+                    cpu_j: last.map_or(0.0, |p| p.cpu_j) + record.joules(record.cpu_power_w),
+                    gpu_j: last.map_or(0.0, |p| p.gpu_j)
+                        + record.joules(record.gpu_power_w.unwrap_or(0.0)),
+                    wall_j: record
+                        .wall_energy_j()
+                        .map(|j| last.and_then(|p| p.wall_j).unwrap_or(0.0) + j),
+                    facility_j: record
+                        .facility_energy_j()
+                        .map(|j| last.and_then(|p| p.facility_j).unwrap_or(0.0) + j),
+                    carbon_g: record
+                        .carbon_g
+                        .map(|g| last.and_then(|p| p.carbon_g).unwrap_or(0.0) + g),
+                    cost: record
+                        .cost
+                        .map(|c| last.and_then(|p| p.cost).unwrap_or(0.0) + c),
+                    water_l: record
+                        .water_l
+                        .map(|l| last.and_then(|p| p.water_l).unwrap_or(0.0) + l),
+                    elapsed_secs: last.map_or(0.0, |p| p.elapsed_secs) + record.interval_secs,
+                });
+            }
+            Scope::Process => {
+                self.latest_process_sample = record.id;
+                let power_w = record.cpu_power_w + record.gpu_power_w.unwrap_or(0.0);
+                let process = self.processes.entry(record.pid).or_insert(Process {
+                    ppid: None,
+                    name: String::new(),
+                    cpu_usage: 0.0,
+                    power_w: 0.0,
+                    energy_j: 0.0,
+                    last_seen: record.id,
+                });
+                process.ppid = record.ppid;
+                if let Some(name) = &record.name {
+                    process.name.clone_from(name);
+                }
+                process.cpu_usage = record.cpu_usage;
+                process.power_w = power_w;
+                process.energy_j += record.joules(power_w);
+                process.last_seen = record.id;
+            }
+        }
+    }
+
+    /// Index past the last sample in view.
+    pub fn view_end(&self) -> usize {
+        self.history.len().saturating_sub(self.offset)
+    }
+
+    /// The last sample in view.
+    pub fn current(&self) -> Option<&Point> {
+        self.history[..self.view_end()].last()
+    }
+
+    /// Energy of the target in view, in kWh.
+    pub fn kwh(&self) -> f64 {
+        self.current()
+            .map_or(0.0, |p| (p.cpu_j + p.gpu_j) / JOULES_PER_KWH)
+    }
+
+    /// Visible rows of the process tree: the processes of the latest per-process sample,
+    /// below their parents, without the descendants of collapsed processes.
+    pub fn tree(&self) -> Vec<TreeRow> {
+        let alive: BTreeSet<u32> = self
+            .processes
+            .iter()
+            .filter(|(_, p)| p.last_seen == self.latest_process_sample)
+            .map(|(pid, _)| *pid)
+            .collect();
+        let mut children: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+        let mut roots = Vec::new();
+        for pid in &alive {
+            match self.processes[pid].ppid.filter(|ppid| alive.contains(ppid)) {
+                Some(ppid) => children.entry(ppid).or_default().push(*pid),
+                None => roots.push(*pid),
+            }
+        }
+
+        let mut rows = Vec::new();
+        let mut stack: Vec<(u32, usize)> = roots.into_iter().rev().map(|pid| (pid, 0)).collect();
+        while let Some((pid, depth)) = stack.pop() {
+            let kids = children.get(&pid);
+            let collapsed = self.collapsed.contains(&pid);
+            rows.push(TreeRow {
+                pid,
+                depth,
+                has_children: kids.is_some(),
+                collapsed,
+            });
+            if let Some(kids) = kids
+                && !collapsed
+            {
+                stack.extend(kids.iter().rev().map(|kid| (*kid, depth + 1)));
+            }
+        }
+        rows
+    }
+
+    pub fn key(&mut self, key: KeyEvent) -> Action {
+        let ctrl_c =
+            key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c');
+        match key.code {
+            _ if ctrl_c => return self.quit(),
+            KeyCode::Char('q') | KeyCode::Esc => return self.quit(),
+            KeyCode::Char('p') => self.toggle_pause(),
+            KeyCode::Tab | KeyCode::BackTab => {
+                self.focus = match self.focus {
+                    Focus::Processes => Focus::Log,
+                    Focus::Log => Focus::Processes,
+                }
+            }
+            KeyCode::Left | KeyCode::Char('h') if self.paused => {
+                self.offset = (self.offset + 1).min(self.history.len().saturating_sub(1));
+            }
+            KeyCode::Right | KeyCode::Char('l') if self.paused => {
+                self.offset = self.offset.saturating_sub(1);
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.scroll(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.scroll(1),
+            KeyCode::PageUp => self.scroll(-10),
+            KeyCode::PageDown => self.scroll(10),
+            KeyCode::Home | KeyCode::Char('g') => self.scroll_to_top(),
+            KeyCode::End | KeyCode::Char('G') => self.scroll_to_bottom(),
+            KeyCode::Enter | KeyCode::Char(' ') => self.toggle_collapsed(),
+            _ => {}
+        }
+        Action::None
+    }
+
+    /// During the run, a first quit stops it; the dashboard closes once it is over.
+    fn quit(&mut self) -> Action {
+        match self.status {
+            Status::Finished => Action::Quit,
+            Status::Running | Status::Stopping => {
+                self.status = Status::Stopping;
+                // Held events include the end of the run
+                if self.paused {
+                    self.toggle_pause();
+                }
+                Action::Stop
+            }
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        if !self.paused {
+            self.offset = 0;
+            for event in std::mem::take(&mut self.held) {
+                self.handle(event);
+            }
+        }
+    }
+
+    fn scroll(&mut self, delta: isize) {
+        match self.focus {
+            Focus::Processes => {
+                let rows = self.tree().len();
+                let selected = self.tree_state.selected().unwrap_or(0);
+                let selected = selected
+                    .saturating_add_signed(delta)
+                    .min(rows.saturating_sub(1));
+                self.tree_state.select(Some(selected));
+            }
+            // Up scrolls back, away from the newest line
+            Focus::Log => {
+                let scroll = self.log_scroll.saturating_add_signed(-delta);
+                self.log_scroll = scroll.min(self.log.len().saturating_sub(1));
+            }
+        }
+    }
+
+    fn scroll_to_top(&mut self) {
+        match self.focus {
+            Focus::Processes => self.tree_state.select(Some(0)),
+            // The top of the log is its oldest line
+            Focus::Log => self.log_scroll = self.log.len().saturating_sub(1),
+        }
+    }
+
+    fn scroll_to_bottom(&mut self) {
+        match self.focus {
+            Focus::Processes => {
+                let rows = self.tree().len();
+                self.tree_state.select(Some(rows.saturating_sub(1)));
+            }
+            Focus::Log => self.log_scroll = 0,
+        }
+    }
+
+    fn toggle_collapsed(&mut self) {
+        if self.focus != Focus::Processes {
+            return;
+        }
+        let rows = self.tree();
+        if let Some(row) = self.tree_state.selected().and_then(|i| rows.get(i))
+            && row.has_children
+            && !self.collapsed.remove(&row.pid)
+        {
+            self.collapsed.insert(row.pid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::testing;
+
+    fn press(app: &mut App, code: KeyCode) {
+        app.key(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+
+    /// A dashboard with a tree of three processes and five log lines.
+    fn app() -> App {
+        let mut app = App::new(testing::run());
+        for (pid, ppid) in [(42, None), (43, Some(42)), (44, Some(42))] {
+            let mut record = testing::record(1);
+            record.scope = Scope::Process;
+            record.pid = pid;
+            record.ppid = ppid;
+            app.handle(Event::Record(Box::new(record)));
+        }
+        for line in 0..5 {
+            app.handle(Event::Log(format!("line {}", line)));
+        }
+        app
+    }
+
+    #[test]
+    fn home_and_end_select_the_first_and_last_process() {
+        let mut app = app();
+        press(&mut app, KeyCode::End);
+        assert_eq!(app.tree_state.selected(), Some(2));
+        press(&mut app, KeyCode::Home);
+        assert_eq!(app.tree_state.selected(), Some(0));
+        press(&mut app, KeyCode::Char('G'));
+        assert_eq!(app.tree_state.selected(), Some(2));
+        press(&mut app, KeyCode::Char('g'));
+        assert_eq!(app.tree_state.selected(), Some(0));
+    }
+
+    #[test]
+    fn home_and_end_scroll_to_the_oldest_and_newest_log_line() {
+        let mut app = app();
+        press(&mut app, KeyCode::Tab);
+        press(&mut app, KeyCode::Home);
+        assert_eq!(app.log_scroll, 4);
+        press(&mut app, KeyCode::End);
+        assert_eq!(app.log_scroll, 0);
+        press(&mut app, KeyCode::Char('g'));
+        assert_eq!(app.log_scroll, 4);
+        press(&mut app, KeyCode::Char('G'));
+        assert_eq!(app.log_scroll, 0);
+    }
+}
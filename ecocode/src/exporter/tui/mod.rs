@@ -0,0 +1,226 @@
+//! TUI exporter — interactive dashboard of the run in the terminal.
+//!
+//! Shows live CPU/GPU power and utilisation sparklines, the monitored process tree and
+//! running energy, cost and CO₂ totals. The dashboard is built from the [`Record`]
+//! stream alone, so a recorded run (see `--replay`) is displayed the same way as a live
+//! one.
+//!
+//! The dashboard runs on its own thread and owns the terminal. While it is open,
+//! EcoCode's own output and that of the measured command are captured and shown in
+//! its log panel. Quitting during a live run stops the run like Ctrl-C; once the run
+//! has finished, the dashboard stays open until it is closed.
+
+mod app;
+mod view;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use ratatui::Terminal;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event as TermEvent, KeyEventKind};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode,
+};
+
+use crate::exporter::{Exporter, ExporterType, Record, RunInfo};
+use crate::summary::Summary;
+use app::{Action, App};
+
+/// How long the dashboard waits for a key before redrawing.
+const FRAME_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What the dashboard thread is told.
+enum Event {
+    Record(Box<Record>),
+    Finished(Box<Summary>),
+    /// A line of captured output.
+    Log(String),
+    /// EcoCode is done with the dashboard, with or without a summary.
+    Ended,
+}
+
+/// The original stdout and stderr, while fd 1 and 2 point at the log pipe.
+struct Capture {
+    stdout: OwnedFd,
+    stderr: OwnedFd,
+}
+
+impl Capture {
+    /// Points stdout and stderr at a pipe whose lines are sent to the dashboard.
+    fn start(events: Sender<Event>) -> io::Result<Capture> {
+        io::stdout().flush()?;
+        io::stderr().flush()?;
+        // SAFETY: plain descriptor syscalls; every new descriptor is owned right away.
+        let (capture, reader) = unsafe {
+            let stdout = dup_cloexec(libc::STDOUT_FILENO)?;
+            let stderr = dup_cloexec(libc::STDERR_FILENO)?;
+            let mut pipe = [0; 2];
+            if libc::pipe2(pipe.as_mut_ptr(), libc::O_CLOEXEC) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let reader = File::from_raw_fd(pipe[0]);
+            let writer = OwnedFd::from_raw_fd(pipe[1]);
+            // The duplicates on 1 and 2 are inherited by the measured command
+            if libc::dup2(writer.as_raw_fd(), libc::STDOUT_FILENO) < 0
+                || libc::dup2(writer.as_raw_fd(), libc::STDERR_FILENO) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            (Capture { stdout, stderr }, reader)
+        };
+        thread::spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                let Ok(line) = line else { break };
+                if events.send(Event::Log(line)).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(capture)
+    }
+
+    /// The terminal the dashboard draws on.
+    fn terminal(&self) -> io::Result<File> {
+        Ok(File::from(self.stdout.try_clone()?))
+    }
+
+    /// Points stdout and stderr back at their originals.
+    fn stop(&self) {
+        let _ = io::stdout().flush();
+        // SAFETY: dup2 onto the standard descriptors, which stay open either way.
+        unsafe {
+            libc::dup2(self.stdout.as_raw_fd(), libc::STDOUT_FILENO);
+            libc::dup2(self.stderr.as_raw_fd(), libc::STDERR_FILENO);
+        }
+    }
+}
+
+/// Duplicates `fd` so that it is not inherited by the measured command.
+///
+/// # Safety
+/// `fd` must be an open descriptor.
+unsafe fn dup_cloexec(fd: i32) -> io::Result<OwnedFd> {
+    let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if dup < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(dup) })
+}
+
+/// Shows the run in an interactive dashboard.
+pub struct TuiExporter {
+    events: Sender<Event>,
+    ui: Option<JoinHandle<io::Result<()>>>,
+    capture: Capture,
+    tty: File,
+}
+
+impl TuiExporter {
+    pub fn new(run: RunInfo) -> Result<TuiExporter, Box<dyn Error>> {
+        // SAFETY: isatty only inspects the descriptor.
+        if unsafe { libc::isatty(libc::STDOUT_FILENO) } != 1 {
+            return Err("the tui output needs stdout to be a terminal".into());
+        }
+        let (events, receiver) = mpsc::channel();
+        let capture = Capture::start(events.clone())?;
+        let tty = capture.terminal()?;
+
+        enable_raw_mode()?;
+        let mut backend = CrosstermBackend::new(capture.terminal()?);
+        execute!(backend, EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(backend)?;
+        terminal.clear()?;
+
+        let ui = thread::spawn(move || run_ui(terminal, App::new(run), receiver));
+        Ok(TuiExporter {
+            events,
+            ui: Some(ui),
+            capture,
+            tty,
+        })
+    }
+
+    fn send(&self, event: Event) {
+        // The dashboard may already be closed, which leaves nothing to update
+        let _ = self.events.send(event);
+    }
+}
+
+/// Dashboard loop: applies the events, draws and handles keys until the user quits.
+fn run_ui(
+    mut terminal: Terminal<CrosstermBackend<File>>,
+    mut app: App,
+    events: Receiver<Event>,
+) -> io::Result<()> {
+    loop {
+        while let Ok(event) = events.try_recv() {
+            app.handle(event);
+        }
+        terminal.draw(|frame| view::draw(frame, &mut app))?;
+
+        if event::poll(FRAME_INTERVAL)?
+            && let TermEvent::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            match app.key(key) {
+                Action::None => {}
+                // Same as Ctrl-C outside the dashboard, which raw mode swallows: a
+                // spawned command gets the signal, a second one stops right away
+                Action::Stop => {
+                    // SAFETY: signals EcoCode itself, whose handler records the request.
+                    unsafe {
+                        libc::kill(libc::getpid(), libc::SIGINT);
+                    }
+                }
+                Action::Quit => return Ok(()),
+            }
+        }
+    }
+}
+
+impl Exporter for TuiExporter {
+    fn exporter_type(&self) -> ExporterType {
+        ExporterType::Tui
+    }
+
+    fn add_record(&mut self, record: Record) -> Result<(), Box<dyn Error>> {
+        self.send(Event::Record(Box::new(record)));
+        Ok(())
+    }
+
+    fn export(&mut self, summary: &Summary) -> Result<(), Box<dyn Error>> {
+        self.send(Event::Finished(Box::new(summary.clone())));
+        Ok(())
+    }
+
+    fn export_line(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+impl Drop for TuiExporter {
+    /// Waits until the dashboard is closed, then gives the terminal back.
+    fn drop(&mut self) {
+        self.send(Event::Ended);
+        let result = self.ui.take().map(|ui| ui.join());
+        let _ = disable_raw_mode();
+        let _ = execute!(
+            self.tty,
+            LeaveAlternateScreen,
+            ratatui::crossterm::cursor::Show
+        );
+        self.capture.stop();
+        match result {
+            Some(Ok(Err(e))) => eprintln!("tui: {}", e),
+            Some(Err(_)) => eprintln!("tui: the dashboard crashed"),
+            _ => {}
+        }
+    }
+}
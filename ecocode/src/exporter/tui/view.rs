@@ -0,0 +1,298 @@
+//! Dashboard layout and widgets.
+
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Sparkline};
+
+use super::app::{App, Focus, Point, Status};
+use crate::footprint::JOULES_PER_KWH;
+
+const KEYS: &str =
+    " q stop/quit  p pause  ←/→ history (paused)  ↑/↓ select  Enter collapse  Tab focus";
+
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let [header, power, usage, middle, log, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(6),
+        Constraint::Length(6),
+        Constraint::Min(8),
+        Constraint::Length(8),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    draw_header(frame, header, app);
+    draw_sparklines(frame, power, usage, app);
+    let [totals, processes] =
+        Layout::horizontal([Constraint::Length(38), Constraint::Min(20)]).areas(middle);
+    draw_totals(frame, totals, app);
+    draw_processes(frame, processes, app);
+    draw_log(frame, log, app);
+    frame.render_widget(Line::from(KEYS).dark_gray(), footer);
+}
+
+fn draw_header(frame: &mut Frame, area: Rect, app: &App) {
+    let (status, color) = match app.status {
+        _ if app.paused => ("PAUSED", Color::Yellow),
+        Status::Running => ("RUNNING", Color::Green),
+        Status::Stopping => ("STOPPING", Color::Yellow),
+        Status::Finished => ("FINISHED", Color::Blue),
+    };
+    let mut spans = vec![
+        Span::styled(" EcoCode ", Style::new().bold().reversed()),
+        Span::styled(format!(" {} ", status), Style::new().fg(color).bold()),
+    ];
+    if app.offset > 0 {
+        spans.push(Span::raw(format!("{} samples back ", app.offset)).yellow());
+    }
+    spans.extend([
+        Span::raw(format!(" {} ", app.run.command)),
+        Span::raw(format!(" run {} on {}", app.run.id, app.run.host)).dark_gray(),
+    ]);
+    frame.render_widget(Line::from(spans), area);
+}
+
+fn draw_sparklines(frame: &mut Frame, power: Rect, usage: Rect, app: &App) {
+    let [cpu_power, gpu_power] = halves(power);
+    let [cpu_usage, gpu_usage] = halves(usage);
+    let current = app.current();
+    let has_gpu = current.is_some_and(|p| p.gpu_w.is_some());
+
+    // Watts as milliwatts, percentages as per mille: sparklines take integers
+    sparkline(
+        frame,
+        cpu_power,
+        app,
+        format!("CPU power {}", fmt_watts(current.map(|p| p.cpu_w))),
+        Color::Cyan,
+        None,
+        |p| Some((p.cpu_w * 1000.0) as u64),
+    );
+    sparkline(
+        frame,
+        cpu_usage,
+        app,
+        format!(
+            "CPU utilisation {}",
+            fmt_percent(current.map(|p| p.cpu_usage))
+        ),
+        Color::Cyan,
+        Some(1000),
+        |p| Some((p.cpu_usage * 10.0) as u64),
+    );
+    if has_gpu {
+        sparkline(
+            frame,
+            gpu_power,
+            app,
+            format!("GPU power {}", fmt_watts(current.and_then(|p| p.gpu_w))),
+            Color::Magenta,
+            None,
+            |p| p.gpu_w.map(|w| (w * 1000.0) as u64),
+        );
+        sparkline(
+            frame,
+            gpu_usage,
+            app,
+            format!(
+                "GPU utilisation {}",
+                fmt_percent(current.and_then(|p| p.gpu_usage))
+            ),
+            Color::Magenta,
+            Some(1000),
+            |p| p.gpu_usage.map(|u| (u * 10.0) as u64),
+        );
+    } else {
+        for area in [gpu_power, gpu_usage] {
+            let block = Block::bordered().title("GPU").dark_gray();
+            frame.render_widget(Paragraph::new("no GPU sensor").block(block), area);
+        }
+    }
+}
+
+fn halves(area: Rect) -> [Rect; 2] {
+    Layout::horizontal([Constraint::Ratio(1, 2), Constraint::Ratio(1, 2)]).areas(area)
+}
+
+/// The samples in view, as many as fit the width of `area`.
+fn sparkline(
+    frame: &mut Frame,
+    area: Rect,
+    app: &App,
+    title: String,
+    color: Color,
+    max: Option<u64>,
+    value: impl Fn(&Point) -> Option<u64>,
+) {
+    let width = area.width.saturating_sub(2) as usize;
+    let end = app.view_end();
+    let data: Vec<Option<u64>> = app.history[end.saturating_sub(width)..end]
+        .iter()
+        .map(value)
+        .collect();
+    let mut widget = Sparkline::default()
+        .block(Block::bordered().title(title))
+        .data(&data)
+        .style(Style::new().fg(color));
+    if let Some(max) = max {
+        widget = widget.max(max);
+    }
+    frame.render_widget(widget, area);
+}
+
+fn draw_totals(frame: &mut Frame, area: Rect, app: &App) {
+    let current = app.current();
+    let mut lines = vec![
+        row(
+            "Duration",
+            format!(
+                "{:.1} s, {} samples",
+                current.map_or(0.0, |p| p.elapsed_secs),
+                app.view_end()
+            ),
+        ),
+        row("CPU", format!("{:.2} J", current.map_or(0.0, |p| p.cpu_j))),
+    ];
+    if current.is_some_and(|p| p.gpu_w.is_some()) {
+        lines.push(row(
+            "GPU",
+            format!("{:.2} J", current.map_or(0.0, |p| p.gpu_j)),
+        ));
+    }
+    let kwh = app.kwh();
+    lines.push(row(
+        "Total",
+        format!("{:.2} J, {:.4} Wh", kwh * JOULES_PER_KWH, kwh * 1000.0),
+    ));
+    if let Some(joules) = current.and_then(|p| p.wall_j) {
+        lines.push(row("Wall", format!("{:.2} J (est.)", joules)));
+    }
+    if let Some(joules) = current.and_then(|p| p.facility_j) {
+        lines.push(row("Facility", format!("{:.2} J (est.)", joules)));
+    }
+    lines.push(match current.and_then(|p| p.carbon_g) {
+        Some(grams) => row("CO₂e", format!("{:.6} g", grams)),
+        None => row("CO₂e", "set --carbon-intensity".dark_gray()),
+    });
+    lines.push(match current.and_then(|p| p.cost) {
+        Some(cost) => row(
+            "Cost",
+            format!(
+                "{:.6} {}",
+                cost,
+                app.run.currency.as_deref().unwrap_or_default()
+            ),
+        ),
+        None => row("Cost", "set --energy-price".dark_gray()),
+    });
+    if let Some(litres) = current.and_then(|p| p.water_l) {
+        lines.push(row("Water", format!("{:.6} L", litres)));
+    }
+    // The summary covers the whole run, so it is only shown at its end
+    if let Some(summary) = app.summary.as_ref().filter(|_| app.offset == 0) {
+        lines.push(Line::raw(""));
+        lines.push(row(
+            "Power",
+            format!(
+                "mean {:.2} W, peak {:.2} W",
+                summary.mean_power_w, summary.peak_power_w
+            ),
+        ));
+        if let Some(exit_code) = summary.exit_code {
+            lines.push(row("Exit code", exit_code.to_string()));
+        }
+        if let Some(error) = &summary.error {
+            lines.push(row("Error", error.clone().red()));
+        }
+    }
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("Totals")),
+        area,
+    );
+}
+
+fn row(name: &str, value: impl Into<Span<'static>>) -> Line<'static> {
+    Line::from(vec![
+        Span::styled(format!(" {:<10}", name), Style::new().bold()),
+        value.into(),
+    ])
+}
+
+fn draw_processes(frame: &mut Frame, area: Rect, app: &mut App) {
+    let block = focus_block("Processes".to_string(), app.focus == Focus::Processes);
+    let rows = app.tree();
+    if rows.is_empty() {
+        let hint = "Run with --per-process to see the process tree";
+        frame.render_widget(Paragraph::new(hint).dark_gray().block(block), area);
+        return;
+    }
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let [header, list_area] =
+        Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(inner);
+
+    // Numbers first, so that the indentation of the tree does not shift the columns
+    let columns = format!(
+        "{:>7} {:>6} {:>10} {:>12}  {}",
+        "PID", "CPU%", "Power", "Energy", "Process"
+    );
+    frame.render_widget(Line::from(columns).bold(), header);
+    let items: Vec<ListItem> = rows
+        .iter()
+        .map(|row| {
+            let process = &app.processes[&row.pid];
+            let marker = match (row.has_children, row.collapsed) {
+                (false, _) => ' ',
+                (true, false) => '▾',
+                (true, true) => '▸',
+            };
+            ListItem::new(format!(
+                "{:>7} {:>5.1}% {:>8.2} W {:>10.1} J  {}{} {}",
+                row.pid,
+                process.cpu_usage,
+                process.power_w,
+                process.energy_j,
+                "  ".repeat(row.depth),
+                marker,
+                process.name
+            ))
+        })
+        .collect();
+    if app.tree_state.selected().is_some_and(|i| i >= items.len()) {
+        app.tree_state.select(Some(items.len() - 1));
+    }
+    let list = List::new(items).highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, list_area, &mut app.tree_state);
+}
+
+fn draw_log(frame: &mut Frame, area: Rect, app: &App) {
+    let height = area.height.saturating_sub(2) as usize;
+    let end = app.log.len().saturating_sub(app.log_scroll);
+    let lines: Vec<Line> = app
+        .log
+        .range(end.saturating_sub(height)..end)
+        .map(|line| Line::raw(line.as_str()))
+        .collect();
+    let block = focus_block("Output".to_string(), app.focus == Focus::Log);
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+/// A bordered block, highlighted when it has the keyboard focus.
+fn focus_block(title: String, focused: bool) -> Block<'static> {
+    let block = Block::bordered().title(title);
+    if focused {
+        block.border_style(Style::new().fg(Color::Yellow))
+    } else {
+        block
+    }
+}
+
+fn fmt_watts(value: Option<f64>) -> String {
+    value.map_or("N/A".to_string(), |w| format!("{:.2} W", w))
+}
+
+fn fmt_percent(value: Option<f64>) -> String {
+    value.map_or("N/A".to_string(), |u| format!("{:.1} %", u))
+}
@@ -0,0 +1,292 @@
+//! Wall and facility power estimated from the measured component power.
+//!
+//! The sensors measure the DC power of the CPU and GPU. What the host draws at the wall
+//! also includes the rest of the system (a static baseline for the board, memory, disks
+//! and fans) and the losses of the power supply, whose efficiency depends on its load.
+//! The facility additionally spends energy on cooling and power distribution, which
+//! the PUE (power usage effectiveness) multiplier accounts for:
+//!
+//! ```text
+//! wall     = (component + baseline × CPU share) / efficiency(host load)
+//! facility = wall × PUE
+//! ```
+//!
+//! The baseline is attributed like the CPU power, by the target's share of the CPU. The
+//! PSU load is the DC power of the whole host (every CPU and GPU sensor plus the
+//! baseline) over the rated capacity of the PSU.
+
+use crate::exporter::Record;
+use crate::sensor::{Component, SensorSample};
+
+/// 80 PLUS efficiency requirements (115 V internal, non-redundant), as (load %,
+/// efficiency) points.
+const PRESETS: &[(&str, &[(f64, f64)])] = &[
+    (
+        "80plus-bronze",
+        &[(20.0, 0.82), (50.0, 0.85), (100.0, 0.82)],
+    ),
+    (
+        "80plus-silver",
+        &[(20.0, 0.85), (50.0, 0.88), (100.0, 0.85)],
+    ),
+    ("80plus-gold", &[(20.0, 0.87), (50.0, 0.90), (100.0, 0.87)]),
+    (
+        "80plus-platinum",
+        &[(20.0, 0.90), (50.0, 0.92), (100.0, 0.89)],
+    ),
+    (
+        "80plus-titanium",
+        &[(10.0, 0.90), (20.0, 0.92), (50.0, 0.94), (100.0, 0.90)],
+    ),
+];
+
+/// Efficiency of the power supply.
+#[derive(Clone, Debug)]
+pub enum Efficiency {
+    Constant(f64),
+    /// (load %, efficiency) points sorted by load, interpolated linearly and held
+    /// constant beyond the first and last point.
+    Curve(Vec<(f64, f64)>),
+}
+
+impl Efficiency {
+    /// Parses `--psu-efficiency`: an efficiency (0-1), a preset of [`PRESETS`] or a curve
+    /// of `load%:efficiency` points separated by commas, e.g. `20:0.88,50:0.92,100:0.89`.
+    pub fn parse(spec: &str) -> Result<Efficiency, String> {
+        if let Ok(efficiency) = spec.parse::<f64>() {
+            return check(efficiency).map(Efficiency::Constant);
+        }
+        if let Some((_, points)) = PRESETS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(spec))
+        {
+            return Ok(Efficiency::Curve(points.to_vec()));
+        }
+        if !spec.contains(':') {
+            let presets: Vec<&str> = PRESETS.iter().map(|(name, _)| *name).collect();
+            return Err(format!(
+                "{} is neither an efficiency, a load:efficiency curve nor a preset (one of {})",
+                spec,
+                presets.join(", ")
+            ));
+        }
+        let mut points = spec
+            .split(',')
+            .map(|point| {
+                let (load, efficiency) = point
+                    .split_once(':')
+                    .ok_or_else(|| format!("expected load%:efficiency, got {}", point))?;
+                let load = load.trim().parse::<f64>().map_err(|e| e.to_string())?;
+                let efficiency = efficiency
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|e| e.to_string())?;
+                if !(load >= 0.0 && load.is_finite()) {
+                    return Err(format!("invalid load {}", load));
+                }
+                Ok((load, check(efficiency)?))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        points.dedup_by(|a, b| a.0 == b.0);
+        Ok(Efficiency::Curve(points))
+    }
+
+    /// Efficiency at `load_percent` of the rated capacity.
+    fn at(&self, load_percent: f64) -> f64 {
+        let points = match self {
+            Efficiency::Constant(efficiency) => return *efficiency,
+            Efficiency::Curve(points) => points,
+        };
+        let next = points.partition_point(|(load, _)| *load < load_percent);
+        match (
+            next.checked_sub(1).map(|i| points[i]),
+            points.get(next).copied(),
+        ) {
+            (Some((load_0, eff_0)), Some((load_1, eff_1))) => {
+                eff_0 + (eff_1 - eff_0) * (load_percent - load_0) / (load_1 - load_0)
+            }
+            (Some((_, efficiency)), None) | (None, Some((_, efficiency))) => efficiency,
+            (None, None) => 1.0,
+        }
+    }
+}
+
+fn check(efficiency: f64) -> Result<f64, String> {
+    if efficiency > 0.0 && efficiency <= 1.0 {
+        Ok(efficiency)
+    } else {
+        Err(format!("efficiency {} is not in (0, 1]", efficiency))
+    }
+}
+
+/// Conversion of the component power to wall and facility power.
+#[derive(Clone, Debug)]
+pub struct Conversion {
+    efficiency: Efficiency,
+    /// Rated output of the power supply in watts, needed by an efficiency curve.
+    capacity_w: Option<f64>,
+    baseline_w: f64,
+    pue: f64,
+}
+
+impl Conversion {
+    /// The conversion with the given factors; absent ones leave the power unchanged.
+    pub fn new(
+        efficiency: Option<Efficiency>,
+        capacity_w: Option<f64>,
+        baseline_w: Option<f64>,
+        pue: Option<f64>,
+    ) -> Result<Conversion, String> {
+        let efficiency = efficiency.unwrap_or(Efficiency::Constant(1.0));
+        if matches!(efficiency, Efficiency::Curve(_)) && capacity_w.is_none() {
+            return Err("a PSU efficiency curve needs --psu-capacity".into());
+        }
+        if capacity_w.is_some_and(|w| !(w > 0.0 && w.is_finite())) {
+            return Err("--psu-capacity must be a positive number of watts".into());
+        }
+        let baseline_w = baseline_w.unwrap_or(0.0);
+        if !(baseline_w >= 0.0 && baseline_w.is_finite()) {
+            return Err("--baseline-power must be a non-negative number of watts".into());
+        }
+        let pue = pue.unwrap_or(1.0);
+        if !(pue >= 1.0 && pue.is_finite()) {
+            return Err("--pue must be at least 1".into());
+        }
+        Ok(Conversion {
+            efficiency,
+            capacity_w,
+            baseline_w,
+            pue,
+        })
+    }
+
+    /// Sets the wall and facility power of `record`, from the `samples` of the whole host.
+    pub fn apply(&self, record: &mut Record, samples: &[SensorSample]) {
+        let host_w: f64 = samples
+            .iter()
+            .filter(|s| s.component != Component::System && s.counts_in_total)
+            .map(|s| s.power_w)
+            .sum();
+        let load_percent = self.capacity_w.map_or(0.0, |capacity| {
+            (host_w + self.baseline_w) / capacity * 100.0
+        });
+
+        let component_w = record.cpu_power_w + record.gpu_power_w.unwrap_or(0.0);
+        let baseline_w = self.baseline_w * record.cpu_usage / 100.0;
+        let wall_w = (component_w + baseline_w) / self.efficiency.at(load_percent);
+        record.wall_power_w = Some(wall_w);
+        record.facility_power_w = Some(wall_w * self.pue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::testing;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn sample(component: Component, power_w: f64) -> SensorSample {
+        SensorSample {
+            name: format!("{:?}", component),
+            component,
+            counts_in_total: true,
+            wrapped: false,
+            energy_j: power_w,
+            power_w,
+            process_usage: None,
+        }
+    }
+
+    #[test]
+    fn curve_is_interpolated_between_points() {
+        let curve = Efficiency::Curve(vec![(20.0, 0.82), (50.0, 0.85), (100.0, 0.82)]);
+        assert_close(curve.at(20.0), 0.82);
+        assert_close(curve.at(35.0), 0.835);
+        assert_close(curve.at(50.0), 0.85);
+        assert_close(curve.at(75.0), 0.835);
+    }
+
+    #[test]
+    fn curve_is_held_constant_beyond_its_ends() {
+        let curve = Efficiency::Curve(vec![(20.0, 0.82), (50.0, 0.85), (100.0, 0.82)]);
+        assert_close(curve.at(0.0), 0.82);
+        assert_close(curve.at(5.0), 0.82);
+        assert_close(curve.at(150.0), 0.82);
+        assert_close(Efficiency::Constant(0.9).at(150.0), 0.9);
+    }
+
+    #[test]
+    fn parses_presets_curves_and_constants() {
+        let Ok(Efficiency::Curve(gold)) = Efficiency::parse("80PLUS-Gold") else {
+            panic!("80plus-gold is not a curve");
+        };
+        assert_eq!(gold, [(20.0, 0.87), (50.0, 0.90), (100.0, 0.87)]);
+        let Ok(Efficiency::Curve(curve)) = Efficiency::parse("100:0.89, 20:0.88,50:0.92") else {
+            panic!("not a curve");
+        };
+        assert_eq!(curve, [(20.0, 0.88), (50.0, 0.92), (100.0, 0.89)]);
+        assert!(matches!(
+            Efficiency::parse("0.9"),
+            Ok(Efficiency::Constant(0.9))
+        ));
+        assert!(Efficiency::parse("1.2").is_err());
+        assert!(Efficiency::parse("20:0").is_err());
+        let unknown = Efficiency::parse("80plus-diamond").unwrap_err();
+        assert!(unknown.contains("80plus-titanium"));
+    }
+
+    #[test]
+    fn applies_efficiency_baseline_and_pue() {
+        let conversion =
+            Conversion::new(Some(Efficiency::Constant(0.9)), None, Some(20.0), Some(1.5)).unwrap();
+        // 10 W of CPU power and half of the 20 W baseline at 50% CPU
+        let mut record = testing::record(1);
+        conversion.apply(&mut record, &[sample(Component::Cpu, 10.0)]);
+        assert_close(record.wall_power_w.unwrap(), 20.0 / 0.9);
+        assert_close(record.facility_power_w.unwrap(), 20.0 / 0.9 * 1.5);
+    }
+
+    #[test]
+    fn curve_efficiency_follows_the_host_load() {
+        let conversion = Conversion::new(
+            Efficiency::parse("80plus-gold").ok(),
+            Some(200.0),
+            Some(10.0),
+            None,
+        )
+        .unwrap();
+        // (90 W of CPU and GPU + 10 W baseline) / 200 W is 50% load; system sensors
+        // overlap the components and are left out
+        let samples = [
+            sample(Component::Cpu, 60.0),
+            sample(Component::Gpu, 30.0),
+            sample(Component::System, 150.0),
+        ];
+        let mut record = testing::record(1);
+        conversion.apply(&mut record, &samples);
+        assert_close(record.wall_power_w.unwrap(), 15.0 / 0.90);
+        assert_close(record.facility_power_w.unwrap(), 15.0 / 0.90);
+    }
+
+    #[test]
+    fn rejects_invalid_factors() {
+        let curve = Efficiency::parse("80plus-gold").ok();
+        assert!(Conversion::new(curve, None, None, None).is_err());
+        assert!(Conversion::new(None, Some(0.0), None, None).is_err());
+        assert!(Conversion::new(None, None, Some(0.0), None).is_ok());
+        assert_eq!(
+            Conversion::new(None, None, Some(-1.0), None).unwrap_err(),
+            "--baseline-power must be a non-negative number of watts"
+        );
+        assert!(Conversion::new(None, None, None, Some(0.9)).is_err());
+    }
+}
@@ -0,0 +1,29 @@
+//! Units and checks shared by the footprint estimates (carbon, cost and water).
+
+pub const JOULES_PER_KWH: f64 = 3_600_000.0;
+
+/// Accepts a footprint factor of `value`, where zero is valid, e.g. for a free tariff or
+/// a dry cooling system; `what` names it in the error.
+pub fn check(what: &str, value: f64) -> Result<f64, String> {
+    if !value.is_finite() {
+        Err(format!("{} {} is not a finite number", what, value))
+    } else if value < 0.0 {
+        Err(format!("{} {} is negative", what, value))
+    } else {
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_accepts_zero_and_rejects_negative_or_non_finite_factors() {
+        assert_eq!(check("WUE", 0.0), Ok(0.0));
+        assert_eq!(check("WUE", 1.8), Ok(1.8));
+        assert_eq!(check("WUE", -1.0), Err("WUE -1 is negative".to_string()));
+        assert!(check("WUE", f64::NAN).is_err());
+        assert!(check("WUE", f64::INFINITY).is_err());
+    }
+}
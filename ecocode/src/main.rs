@@ -1,177 +1,675 @@
-use clap::Parser;
-use nvml_wrapper::Nvml;
-use std::fs::File;
-use std::io::BufReader;
+use clap::{ArgGroup, Parser};
 use std::process::Command;
-use std::thread;
 use std::time::{Duration, Instant, SystemTime};
 use sysinfo::{Pid, ProcessesToUpdate, RefreshKind, System};
+mod carbon;
+mod cost;
 mod exporter;
+mod facility;
+mod footprint;
+mod replay;
 mod sensor;
-use sensor::RAPL_PATH;
-use sensor::cpu::get_energy;
-use sensor::gpu::DEFAULT_GPU_DEVICE_INDEX;
-use sensor::gpu::{get_gpu_energy, get_gpu_energy_by_pid};
+mod shutdown;
+mod summary;
+mod target;
+mod water;
+use carbon::Intensity;
+use cost::Tariff;
+use facility::{Conversion, Efficiency};
+use sensor::{
+    Attribution, Component, SYSFS_ROOT, SensorRegistry, SensorSample, attributed_domains,
+    attributed_power, process_usage,
+};
+use shutdown::Shutdown;
+use std::path::{Path, PathBuf};
+use summary::EnergyMeter;
+use target::cgroup::{CGROUP_ROOT, Cgroup};
+use target::{Lifetime, Tracker};
+use water::WaterFactors;
 
+#[cfg(feature = "columnar")]
+use exporter::columnar::{ColumnarExporter, Format};
+use exporter::composite::CompositeExporter;
 use exporter::csv::CsvExporter;
-use exporter::terminal::TerminalExporter;
+use exporter::influx::InfluxExporter;
 use exporter::json::JsonExporter;
+use exporter::ndjson::NdjsonExporter;
+#[cfg(feature = "otlp")]
+use exporter::otlp::{self, OtlpExporter};
+use exporter::prometheus::{DEFAULT_LISTEN_ADDR, PrometheusExporter};
 use exporter::sqlite::SqliteExporter;
-use exporter::{Exporter, Record};
+use exporter::terminal::TerminalExporter;
+use exporter::textfile::TextfileExporter;
+use exporter::tui::TuiExporter;
+use exporter::{Exporter, Hardware, Record, RunInfo, Scope};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(group(
+    ArgGroup::new("target")
+        .required(true)
+        .args(["command", "pid", "name", "unit", "container", "cgroup_path", "replay"])
+))]
 struct Args {
-    /// Output format: "terminal" or "csv"
-    #[arg(short, long, default_value = "terminal")]
-    output: String,
+    /// Output as TYPE or TYPE=DEST, repeatable (e.g. -o terminal -o sqlite=runs.db).
+    /// TYPE is "terminal", "tui", "csv", "json", "ndjson", "influx", "sqlite",
+    /// "prometheus", "textfile", "parquet" and "arrow" when built with the `columnar`
+    /// feature, or "otlp" with the `otlp` feature; DEST is the output file, the .prom
+    /// file or collector directory (textfile), the listen address (prometheus) or the
+    /// collector URL (otlp, default http://localhost:4318). ndjson and influx also take
+    /// "-" for stdout, which moves all other output to stderr; ndjson takes "unix:PATH"
+    /// for a Unix socket and influx "udp://HOST:PORT" or an http(s) write URL (token
+    /// from INFLUX_TOKEN)
+    #[arg(short, long, value_name = "TYPE[=DEST]", default_value = "terminal")]
+    output: Vec<String>,
+
+    /// Label attached to the run in the exported data (repeatable)
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_label)]
+    label: Vec<(String, String)>,
 
-    /// CSV output file path (required if output=csv)
+    /// Destination of outputs given without one
     #[arg(short, long)]
     file: Option<String>,
 
+    /// Address of the /metrics endpoint when output=prometheus has no destination
+    #[arg(long, default_value = DEFAULT_LISTEN_ADDR)]
+    listen: String,
+
+    /// Seconds the /metrics endpoint stays up after the run until the summary has been
+    /// scraped once (0 stops right away)
+    #[arg(long, value_name = "SECONDS", default_value = "30")]
+    linger: u64,
+
     /// Measurement interval in seconds
     #[arg(short, long, default_value = "1")]
     interval: u64,
+
+    /// sysfs mount point used to discover RAPL zones and hwmon sensors
+    #[arg(long, default_value = SYSFS_ROOT)]
+    sysfs_root: PathBuf,
+
+    /// Also emit one row per process of the monitored tree, next to the totals
+    #[arg(long)]
+    per_process: bool,
+
+    /// Launch the command in a dedicated cgroup v2 for exact CPU accounting of every
+    /// descendant, including short-lived ones
+    #[arg(long, conflicts_with_all = ["pid", "name", "unit", "container", "cgroup_path", "replay"])]
+    cgroup: bool,
+
+    /// cgroup v2 mount point: the dedicated cgroup is created below it, and units,
+    /// containers and cgroup paths are looked up in it (formerly --cgroup-parent)
+    #[arg(long, alias = "cgroup-parent", default_value = CGROUP_ROOT)]
+    cgroup_root: PathBuf,
+
+    /// Attach to an already running process instead of spawning a command (repeatable)
+    #[arg(long, value_name = "PID")]
+    pid: Vec<u32>,
+
+    /// Attach to the running processes whose name contains PATTERN
+    #[arg(long, value_name = "PATTERN")]
+    name: Option<String>,
+
+    /// Attach to the processes of a systemd unit (e.g. nginx or nginx.service)
+    #[arg(long, value_name = "UNIT")]
+    unit: Option<String>,
+
+    /// Attach to the processes of a container, by full or abbreviated ID
+    #[arg(long, value_name = "ID")]
+    container: Option<String>,
+
+    /// Attach to the processes of a cgroup, absolute or relative to --cgroup-root
+    #[arg(long, value_name = "PATH")]
+    cgroup_path: Option<PathBuf>,
+
+    /// Feed a run recorded by the ndjson or json output to the outputs instead of
+    /// measuring (e.g. -o tui --replay run.ndjson)
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+
+    /// Do not relay SIGINT/SIGTERM to the spawned command: stop measuring after the
+    /// current sample instead of waiting for the command to exit
+    #[arg(long)]
+    no_forward_signals: bool,
+
+    /// Stop monitoring attached processes after this many seconds
+    #[arg(long, value_name = "SECONDS", conflicts_with = "command")]
+    duration: Option<u64>,
+
+    /// Grid carbon intensity for the emissions of every sample and of the run: gCO2e/kWh,
+    /// a country or region code with a bundled yearly average (e.g. FR, US-CA) or a CSV
+    /// file of timestamp,g_per_kwh lines
+    #[arg(long, value_name = "G_PER_KWH|REGION|FILE", value_parser = Intensity::parse)]
+    carbon_intensity: Option<Intensity>,
+
+    /// Energy price for the cost of every sample and of the run: a price per kWh or a
+    /// time-of-use tariff file of days,hours,price lines (e.g. mon-fri,8-20,0.32)
+    #[arg(long, value_name = "PRICE|FILE", value_parser = Tariff::parse)]
+    energy_price: Option<Tariff>,
+
+    /// Currency of --energy-price, reported with the costs
+    #[arg(long, value_name = "CODE", default_value = "USD", value_parser = cost::parse_currency)]
+    currency: String,
+
+    /// PSU efficiency for the estimated wall power: a constant (e.g. 0.92), an 80 PLUS
+    /// preset (80plus-bronze to 80plus-titanium) or a curve of load%:efficiency points
+    /// (e.g. 20:0.88,50:0.92,100:0.89), which needs --psu-capacity
+    #[arg(
+        long,
+        value_name = "EFFICIENCY|PRESET|CURVE",
+        value_parser = Efficiency::parse,
+        conflicts_with = "replay"
+    )]
+    psu_efficiency: Option<Efficiency>,
+
+    /// Rated output of the PSU in watts, for the load of an efficiency curve
+    #[arg(long, value_name = "WATTS", conflicts_with = "replay")]
+    psu_capacity: Option<f64>,
+
+    /// DC power of the rest of the system (board, memory, disks, fans) in watts, added
+    /// to the estimated wall power by the target's share of the CPU
+    #[arg(long, value_name = "WATTS", conflicts_with = "replay")]
+    baseline_power: Option<f64>,
+
+    /// Power usage effectiveness of the data centre, for the estimated facility power
+    #[arg(long, value_name = "FACTOR", conflicts_with = "replay")]
+    pue: Option<f64>,
+
+    /// On-site water usage effectiveness of the data centre in L/kWh of IT energy, for
+    /// the water footprint of every sample and of the run, or a country or region code
+    /// with a bundled estimate from its climate (e.g. IE, US-AZ)
+    #[arg(long, value_name = "L_PER_KWH|REGION", value_parser = water::parse_wue)]
+    wue: Option<f64>,
+
+    /// Off-site water consumed by electricity generation for the water footprint: L/kWh
+    /// or a country or region code with a bundled estimate (e.g. FR, US-CA)
+    #[arg(long, value_name = "L_PER_KWH|REGION", value_parser = water::parse_intensity)]
+    water_intensity: Option<f64>,
+
     /// Command to monitor (with its arguments)
-    #[arg(trailing_var_arg = true, required = true)]
+    #[arg(trailing_var_arg = true)]
     command: Vec<String>,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Args = Args::parse();
 
-    let interval = args.interval;
-
-    // --- Exporter setup ---
-    let mut exporter: Box<dyn Exporter> = match args.output.as_str() {
-        "terminal" => Box::new(TerminalExporter::new()),
-        "csv" => Box::new(CsvExporter::new(args.file.unwrap())?),
-        "json" => Box::new(JsonExporter::new(args.file.unwrap())?),
-        "sqlite" => Box::new(SqliteExporter::new(args.file.unwrap())),
-        _ => Box::new(TerminalExporter::new()),
+    // NDJSON or line protocol on stdout: claim stdout for the records before anything
+    // is printed
+    let streams = |kind: &str| matches!(kind, "ndjson" | "influx");
+    let to_stdout = |spec: &String| match spec.split_once('=') {
+        Some((kind, dest)) => streams(kind) && dest == exporter::STDOUT,
+        None => streams(spec) && args.file.as_deref() == Some(exporter::STDOUT),
     };
-    println!("Exporter type: {:?}", exporter.exporter_type());
+    if args.output.iter().any(to_stdout) {
+        exporter::redirect_stdout()?;
+    }
 
-    // --- Spawn the target process ---
-    let command = Command::new(&args.command[0])
-        .args(&args.command[1..])
-        .spawn()
-        .expect("failed to execute process");
+    if let Some(path) = &args.replay {
+        return replay(&args, path);
+    }
 
-    let pid = Pid::from(command.id() as usize);
+    let interval = args.interval;
+    let conversion = if args.psu_efficiency.is_some()
+        || args.psu_capacity.is_some()
+        || args.baseline_power.is_some()
+        || args.pue.is_some()
+    {
+        Some(Conversion::new(
+            args.psu_efficiency.clone(),
+            args.psu_capacity,
+            args.baseline_power,
+            args.pue,
+        )?)
+    } else {
+        None
+    };
+
+    // --- Sensor setup ---
+    // Every available backend (RAPL, hwmon, NVML, ...) is probed; the GPU is optional
+    // and without one the run continues in CPU-only mode.
+    let mut sensors = SensorRegistry::probe(&args.sysfs_root);
+    if !sensors.has(Component::Cpu) {
+        return Err("no CPU energy sensor available (RAPL requires read access, try sudo)".into());
+    }
+    eprintln!("Sensors: {}", sensors.names().join(", "));
 
     // --- System setup ---
     let mut sys = System::new_with_specifics(RefreshKind::everything());
-    let num_cores = sys.cpus().len();
+    sys.refresh_processes(ProcessesToUpdate::All, true);
 
-    let mut cpu_usage;
+    // --- Exporter setup ---
+    let started_at = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_millis() as i64;
+    let run = RunInfo {
+        id: format!("{}-{}", started_at, std::process::id()),
+        command: describe_target(&args, &sys),
+        args: args.command.clone(),
+        started_at,
+        host: System::host_name().unwrap_or_default(),
+        hardware: Hardware {
+            cpu: sys
+                .cpus()
+                .first()
+                .map(|cpu| cpu.brand().to_string())
+                .unwrap_or_default(),
+            cores: sys.cpus().len(),
+            sensors: sensors.names(),
+        },
+        labels: args.label.iter().cloned().collect(),
+        currency: args.energy_price.as_ref().map(|_| args.currency.clone()),
+    };
+    let mut exporter = build_exporters(&args, &run)?;
 
-    // --- NVML / GPU setup ---
-    let nvml = match Nvml::init() {
-        Ok(n) => n,
-        Err(e) => {
-            eprintln!("Error initializing NVML: {}", e);
-            return Err(Box::new(e));
-        }
+    // --- Signal handling: SIGINT/SIGTERM end the run cleanly ---
+    let shutdown = Shutdown::install()?;
+
+    // --- Target setup: spawn the command, or attach to running processes or a cgroup ---
+    let mut cgroup = None;
+    let existing = if let Some(unit) = &args.unit {
+        Some(Cgroup::for_unit(&args.cgroup_root, unit)?)
+    } else if let Some(id) = &args.container {
+        Some(Cgroup::for_container(&args.cgroup_root, id)?)
+    } else if let Some(path) = &args.cgroup_path {
+        Some(Cgroup::at(&args.cgroup_root, path)?)
+    } else {
+        None
     };
-    // Get the GPU device (default index 0)
-    let device = match nvml.device_by_index(DEFAULT_GPU_DEVICE_INDEX) {
-        Ok(d) => d,
-        Err(e) => {
-            eprintln!("Error getting GPU device: {}", e);
-            return Err(Box::new(e));
+    let mut lifetime = if let Some(existing) = existing {
+        let Some(&pid) = existing.pids()?.first() else {
+            return Err(format!("cgroup {} has no processes", existing.path().display()).into());
+        };
+        eprintln!("Attaching to cgroup {}", existing.path().display());
+        // One handle is sampled by the tracker, the other watched for the end of the run
+        cgroup = Some(Cgroup::open(existing.path())?);
+        Lifetime::Cgroup {
+            cgroup: existing,
+            pid,
+        }
+    } else if args.command.is_empty() {
+        let pids = match &args.name {
+            Some(pattern) => target::find_by_name(&sys, pattern),
+            None => args.pid.iter().map(|p| Pid::from_u32(*p)).collect(),
+        };
+        if let Some(missing) = pids.iter().find(|p| sys.process(**p).is_none()) {
+            return Err(format!("no running process with PID {}", missing).into());
         }
+        if pids.is_empty() {
+            return Err("no running process matches the given name".into());
+        }
+        let pids: Vec<u32> = pids.iter().map(|p| p.as_u32()).collect();
+        eprintln!("Attaching to PIDs {:?}", pids);
+        Lifetime::Attached(pids)
+    } else {
+        let mut command = Command::new(&args.command[0]);
+        command.args(&args.command[1..]);
+        if args.cgroup {
+            let created = Cgroup::create(&args.cgroup_root)?;
+            created.spawn_into(&mut command)?;
+            eprintln!("cgroup: {}", created.path().display());
+            cgroup = Some(created);
+        }
+        Lifetime::Child(command.spawn().expect("failed to execute process"))
     };
+    // Early returns on sensor/exporter errors below leave a spawned child running on
+    // purpose.
+    let pid = lifetime.pid();
+
     // --- Measurement state ---
     let mut iteration = 0;
-    
-    // Initial timestamp in microseconds for NVML (0 targets all samples initially)
-    let mut timestamp: u64 = 0;
+    let mut meter = EnergyMeter::with_currency(run.currency.as_deref());
+    let mut forwarded = false;
+    let deadline = args
+        .duration
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
 
-    //sudo chmod +r /sys/class/powercap/intel-rapl/intel-rapl:0/energy_uj
-    let mut rapl_file = BufReader::new(File::open(RAPL_PATH)?);
-
-    // Take initial energy readings before the loop so that the second reading
+    // Take initial readings before the loop so that the second reading
     // of each iteration can be reused as the first reading of the next one.
-    sys.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
-    let mut cpu_energy_1 = get_energy(&mut rapl_file)?;
-    let mut gpu_energy_1 = get_gpu_energy(&device)?;
-
+    sys.refresh_processes(ProcessesToUpdate::All, true);
+    let mut tracker = match cgroup {
+        Some(cgroup) => Tracker::cgroup(cgroup)?,
+        None => Tracker::Tree {
+            roots: lifetime.roots(),
+        },
+    };
+    sensors.start()?;
 
     // --- Main measurement loop ---
-    loop {
+    // Errors end the loop rather than the function so that what was measured up to
+    // them is still exported
+    let measured = (|| -> Result<i32, Box<dyn std::error::Error>> {
+        Ok(loop {
+            iteration += 1;
 
-        iteration += 1;
+            let start_time = Instant::now();
+            // Sleep for the specified interval, or until the target exits or the
+            // requested duration is over
+            let mut wait = Duration::from_secs(interval);
+            if let Some(deadline) = deadline {
+                wait = wait.min(deadline.saturating_duration_since(start_time));
+            }
+            let exited = lifetime.wait(wait, &shutdown);
+            let timed_out = deadline.is_some_and(|d| Instant::now() >= d);
 
-        let start_time = Instant::now();
-        thread::sleep(Duration::from_secs(interval)); // Sleep for the specified interval
+            // Refresh process data: the whole target is measured, i.e. the root and
+            // every descendant (or every process of the cgroup)
+            let elapsed_secs = start_time.elapsed().as_secs_f64();
+            let members = tracker.sample(&mut sys, elapsed_secs, args.per_process)?;
+            let pids = &members.pids;
 
-        // Refresh process data
-        sys.refresh_processes(ProcessesToUpdate::All, true);
-
-        // Check if the process is still running
-        cpu_usage = if sys.process(pid).is_none() {
-            println!("Process {} finished", pid);
-            break;
-        } else {
-            // cpu_usage() returns total % across all cores; divide by num_cores
-            // to get a normalized 0–100% value for the whole system
-            sys.process(pid).unwrap().cpu_usage() / num_cores as f32
-        };
-        
-
-        // --- CPU energy calculation ---
-        let cpu_energy_2 = get_energy(&mut rapl_file).unwrap();
-        let elapsed_secs = start_time.elapsed().as_secs_f64();
+            // --- Energy calculation ---
+            let samples = sensors.sample(pids, elapsed_secs)?;
+            let timestamp = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_millis() as i64;
 
-        // dbg!(elapsed_secs);
+            // --- Export the measurement records ---
+            let total = Attribution {
+                pids,
+                cpu_share: members.cpu_share,
+            };
+            meter.add(&samples, &total, elapsed_secs);
+            let mut record = build_record(
+                iteration,
+                pid,
+                Scope::Total,
+                timestamp,
+                elapsed_secs,
+                &samples,
+                &total,
+            );
+            if let Some(conversion) = &conversion {
+                conversion.apply(&mut record, &samples);
+            }
+            add_footprint(&args, &mut record);
+            meter.add_footprint(&record);
+            exporter.add_record(record)?;
+            exporter.export_line()?;
 
-        let delta_cpu_energy_mj = (cpu_energy_2 - cpu_energy_1) / 1000.0; // µJ → mJ
-        let cpu_energy_w = (delta_cpu_energy_mj / 1000.0) / elapsed_secs; // (mJ -> J) / s = W
-        let cpu_energy_per_pid = cpu_energy_w * (cpu_usage as f64 / 100.0); // Normalize to 0-1
+            if args.per_process {
+                for (member, cpu_share) in &members.processes {
+                    let single = Attribution {
+                        pids: std::slice::from_ref(member),
+                        cpu_share: *cpu_share,
+                    };
+                    let mut record = build_record(
+                        iteration,
+                        *member,
+                        Scope::Process,
+                        timestamp,
+                        elapsed_secs,
+                        &samples,
+                        &single,
+                    );
+                    if let Some(process) = sys.process(Pid::from_u32(*member)) {
+                        record.ppid = process.parent().map(|parent| parent.as_u32());
+                        record.name = Some(process.name().to_string_lossy().into_owned());
+                    }
+                    if let Some(conversion) = &conversion {
+                        conversion.apply(&mut record, &samples);
+                    }
+                    add_footprint(&args, &mut record);
+                    exporter.add_record(record)?;
+                    exporter.export_line()?;
+                }
+            }
+            exporter.end_interval()?;
 
-        // --- GPU energy calculation ---
-        let gpu_energy_2 = get_gpu_energy(&device)?;
+            if exited || timed_out {
+                break lifetime.finish()?;
+            }
 
-        let (gpu_power_pid, gpu_util_pid, next_timestamp) = get_gpu_energy_by_pid(
-            &device,
-            pid.as_u32(),
-            gpu_energy_1,
-            gpu_energy_2,
-            timestamp,
-            elapsed_secs,
-        );
+            // On SIGINT/SIGTERM a spawned command is given the signal and measured until it
+            // exits; a second signal, or any signal in other modes, stops right away
+            if let Some(request) = shutdown.take() {
+                if lifetime.is_child() && !args.no_forward_signals && !forwarded {
+                    // A signal from the terminal already reached the whole process group
+                    if !request.from_terminal {
+                        lifetime.signal(request.signal)?;
+                    }
+                    eprintln!(
+                        "Received {}, waiting for process {} to exit",
+                        request.signal_name(),
+                        pid
+                    );
+                    forwarded = true;
+                } else {
+                    eprintln!("Received {}, stopping", request.signal_name());
+                    break lifetime.abandon(request.signal)?;
+                }
+            }
+        })
+    })();
 
-        // Carry forward: the end-of-interval reading becomes the start of the next interval
-        cpu_energy_1 = cpu_energy_2;
-        gpu_energy_1 = gpu_energy_2;
-        timestamp = next_timestamp;
+    // Export final results
+    let exit_code = match measured {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            // The outputs still get what was measured, with the error as the reason the
+            // run ended and the command's exit code only if it is known
+            let summary = meter.failed_summary(pid, lifetime.try_finish(), e.to_string());
+            if let Err(export) = exporter.export(&summary) {
+                eprintln!("Error: {}", export);
+            }
+            return Err(e);
+        }
+    };
+    exporter.export(&meter.summary(pid, exit_code))?;
+    drop(exporter);
+    drop(tracker);
 
+    // Propagate the child's exit status as our own
+    std::process::exit(exit_code);
+}
 
-        // --- Export the measurement record ---
-        let record = Record::new(
-            iteration,
-            pid.as_u32(),
-            SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)?
-                .as_millis() as i64,
-            cpu_usage as f64,
-            cpu_energy_per_pid,
-            gpu_util_pid,
-            gpu_power_pid,
-        );
+/// Feeds a recorded run to the outputs instead of measuring.
+fn replay(args: &Args, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut recording = replay::read(path)?;
+    // Emissions, costs and water are recomputed when given, e.g. for another region or
+    // tariff
+    let currency = match &args.energy_price {
+        Some(_) => Some(args.currency.clone()),
+        None => recording.summary.cost.as_ref().map(|c| c.currency.clone()),
+    };
+    let water = args.wue.is_some() || args.water_intensity.is_some();
+    if args.carbon_intensity.is_some() || args.energy_price.is_some() || water {
+        let mut meter = EnergyMeter::with_currency(currency.as_deref());
+        for record in &mut recording.records {
+            add_footprint(args, record);
+            if record.scope == Scope::Total {
+                meter.add_record(record);
+            }
+        }
+        let footprint = meter.summary(0, 0);
+        if args.carbon_intensity.is_some() {
+            recording.summary.carbon = footprint.carbon;
+        }
+        if args.energy_price.is_some() {
+            recording.summary.cost = footprint.cost;
+        }
+        if water {
+            recording.summary.water = footprint.water;
+        }
+    }
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_millis() as i64;
+    let run = RunInfo {
+        id: format!("{}-{}", now, std::process::id()),
+        command: format!("replay of {}", path.display()),
+        args: Vec::new(),
+        started_at: recording.records.first().map_or(now, |r| r.timestamp),
+        host: System::host_name().unwrap_or_default(),
+        hardware: Hardware::default(),
+        labels: args.label.iter().cloned().collect(),
+        currency,
+    };
+    let mut exporter = build_exporters(args, &run)?;
+    let mut records = recording.records.into_iter().peekable();
+    while let Some(record) = records.next() {
+        let id = record.id;
         exporter.add_record(record)?;
         exporter.export_line()?;
+        // The rows of a sample share its ID
+        if records.peek().is_none_or(|next| next.id != id) {
+            exporter.end_interval()?;
+        }
+    }
+    exporter.export(&recording.summary)?;
+    Ok(())
+}
+
+/// Builds every requested output, combined when there are several.
+fn build_exporters(
+    args: &Args,
+    run: &RunInfo,
+) -> Result<Box<dyn Exporter>, Box<dyn std::error::Error>> {
+    let mut sinks = Vec::new();
+    for spec in &args.output {
+        let sink =
+            build_exporter(spec, args, run).map_err(|e| format!("output {}: {}", spec, e))?;
+        eprintln!("Exporter type: {:?}", sink.exporter_type());
+        sinks.push(sink);
+    }
+    Ok(if sinks.len() == 1 {
+        sinks.pop().unwrap()
+    } else {
+        Box::new(CompositeExporter::new(sinks))
+    })
+}
+
+fn parse_label(label: &str) -> Result<(String, String), String> {
+    match label.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got {}", label)),
+    }
+}
 
-        if cpu_usage <= 0.0 && gpu_util_pid <= 0.0 && gpu_power_pid <= 0.0 {
-            println!("Process finished");
-            break;
+/// Builds the exporter for one `--output` spec, `TYPE` or `TYPE=DEST`.
+fn build_exporter(
+    spec: &str,
+    args: &Args,
+    run: &RunInfo,
+) -> Result<Box<dyn Exporter>, Box<dyn std::error::Error>> {
+    let (kind, dest) = match spec.split_once('=') {
+        Some((kind, dest)) => (kind, Some(dest)),
+        None => (spec, None),
+    };
+    let file = || {
+        dest.or(args.file.as_deref())
+            .map(str::to_string)
+            .ok_or(format!("output {} needs a destination file", kind))
+    };
+    Ok(match kind {
+        "terminal" => Box::new(TerminalExporter::new()),
+        "tui" => Box::new(TuiExporter::new(run.clone())?),
+        "csv" => Box::new(CsvExporter::new(file()?)?),
+        "json" => Box::new(JsonExporter::new(file()?)?),
+        "ndjson" => Box::new(NdjsonExporter::new(file()?)?),
+        "influx" => Box::new(InfluxExporter::new(file()?, run.clone())?),
+        #[cfg(feature = "columnar")]
+        "parquet" => Box::new(ColumnarExporter::new(
+            file()?,
+            Format::Parquet,
+            run.clone(),
+        )?),
+        #[cfg(feature = "columnar")]
+        "arrow" => Box::new(ColumnarExporter::new(
+            file()?,
+            Format::ArrowIpc,
+            run.clone(),
+        )?),
+        #[cfg(not(feature = "columnar"))]
+        "parquet" | "arrow" => {
+            return Err(format!("{} output requires the `columnar` feature", kind).into());
         }
+        "sqlite" => Box::new(SqliteExporter::new(file()?, run.clone())?),
+        #[cfg(feature = "otlp")]
+        "otlp" => Box::new(OtlpExporter::new(
+            dest.unwrap_or(otlp::DEFAULT_ENDPOINT),
+            run,
+        )?),
+        #[cfg(not(feature = "otlp"))]
+        "otlp" => return Err("otlp output requires the `otlp` feature".into()),
+        "prometheus" => Box::new(PrometheusExporter::new(
+            dest.unwrap_or(&args.listen),
+            run.clone(),
+            Duration::from_secs(args.linger),
+        )?),
+        "textfile" => Box::new(TextfileExporter::new(file()?, run.clone())?),
+        _ => return Err(format!("unknown output type {}", kind).into()),
+    })
+}
+
+/// Describes the target for the `command` label of the exported metrics: the command
+/// line, or how running processes were selected.
+fn describe_target(args: &Args, sys: &System) -> String {
+    if !args.command.is_empty() {
+        args.command.join(" ")
+    } else if let Some(pattern) = &args.name {
+        pattern.clone()
+    } else if let Some(unit) = &args.unit {
+        unit.clone()
+    } else if let Some(id) = &args.container {
+        id.clone()
+    } else if let Some(path) = &args.cgroup_path {
+        path.display().to_string()
+    } else {
+        args.pid
+            .iter()
+            .map(|pid| match sys.process(Pid::from_u32(*pid)) {
+                Some(process) => process.name().to_string_lossy().into_owned(),
+                None => pid.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
     }
+}
 
-    // Export final results
-    exporter.export()?;
+/// Sets the emissions, cost and water of the record's energy, as far as they are
+/// configured.
+fn add_footprint(args: &Args, record: &mut Record) {
+    if let Some(intensity) = &args.carbon_intensity {
+        intensity.apply(record);
+    }
+    if let Some(tariff) = &args.energy_price {
+        tariff.apply(record);
+    }
+    if args.wue.is_some() || args.water_intensity.is_some() {
+        WaterFactors::new(args.wue, args.water_intensity).apply(record);
+    }
+}
 
-    Ok(())
+/// Builds the record of one sample for the processes of `target`.
+fn build_record(
+    id: u32,
+    pid: u32,
+    scope: Scope,
+    timestamp: i64,
+    interval_secs: f64,
+    samples: &[SensorSample],
+    target: &Attribution,
+) -> Record {
+    Record {
+        id,
+        pid,
+        scope,
+        ppid: None,
+        name: None,
+        timestamp,
+        interval_secs,
+        cpu_usage: target.cpu_share * 100.0,
+        cpu_power_w: attributed_power(samples, Component::Cpu, target).unwrap_or(0.0),
+        cpu_domains: attributed_domains(samples, Component::Cpu, target),
+        gpu_usage: process_usage(samples, Component::Gpu, target.pids),
+        gpu_power_w: attributed_power(samples, Component::Gpu, target),
+        system_power_w: attributed_power(samples, Component::System, target),
+        wall_power_w: None,
+        facility_power_w: None,
+        carbon_g: None,
+        cost: None,
+        water_l: None,
+        counter_wrapped: samples.iter().any(|s| s.wrapped),
+    }
 }
@@ -0,0 +1,88 @@
+//! Replay of recorded runs.
+//!
+//! A run recorded by the `ndjson` or `json` output can be fed to any output again, e.g.
+//! to browse it in the `tui` dashboard or to convert it to another format. The recorded
+//! summary is used when there is one (the last NDJSON line, or the `.summary.json`
+//! sidecar of a JSON file); otherwise it is rebuilt from the records.
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::exporter::{Record, Scope, summary_path};
+use crate::summary::{EnergyMeter, Summary};
+
+/// A recorded run.
+pub struct Recording {
+    pub records: Vec<Record>,
+    pub summary: Summary,
+}
+
+/// A line of an NDJSON recording.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Summary { summary: Box<Summary> },
+    Record(Box<Record>),
+}
+
+/// Reads the NDJSON or JSON recording at `path`.
+pub fn read(path: &Path) -> Result<Recording, Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    let mut summary = None;
+    let records: Vec<Record> = if text.trim_start().starts_with('[') {
+        let sidecar = summary_path(&path.to_string_lossy());
+        if let Ok(json) = fs::read_to_string(sidecar) {
+            summary = Some(serde_json::from_str(&json)?);
+        }
+        serde_json::from_str(&text)?
+    } else {
+        let mut records = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line)
+                .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?
+            {
+                Line::Summary { summary: s } => summary = Some(*s),
+                Line::Record(record) => records.push(*record),
+            }
+        }
+        records
+    };
+
+    // A recording cut short (e.g. by SIGKILL) has no summary
+    let summary = summary.unwrap_or_else(|| {
+        let mut meter = EnergyMeter::new();
+        for record in records.iter().filter(|r| r.scope == Scope::Total) {
+            meter.add_record(record);
+        }
+        meter.summary(records.first().map_or(0, |r| r.pid), 0)
+    });
+    Ok(Recording { records, summary })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_recordings_with_the_old_power_field_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("old.ndjson");
+        let line = r#"{"id":1,"pid":42,"scope":"total","timestamp":1700000001000,"interval_secs":2.0,"cpu_usage":50.0,"cpu_energy":10.0,"cpu_domains":{},"gpu_usage":20.0,"gpu_energy":5.0,"system_energy":30.0,"counter_wrapped":false}"#;
+        fs::write(&path, format!("{}\n", line)).unwrap();
+
+        let recording = read(&path).unwrap();
+        let record = &recording.records[0];
+        assert_eq!(record.cpu_power_w, 10.0);
+        assert_eq!(record.gpu_power_w, Some(5.0));
+        assert_eq!(record.system_power_w, Some(30.0));
+        // Without a summary line it is rebuilt from the records
+        assert_eq!(recording.summary.pid, 42);
+        assert_eq!(recording.summary.total_energy.joules, 30.0);
+    }
+}
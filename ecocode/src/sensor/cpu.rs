@@ -2,57 +2,230 @@
 //!
 //! This module provides functionality to read CPU energy consumption from the system's
 //! Intel RAPL interface. Energy values are measured in microjoules.
+//!
+//! RAPL zones are discovered under the powercap sysfs class: every `intel-rapl:N`
+//! (one package per socket, or `psys`) and `intel-rapl:N:M` subzone (`core`, `uncore`,
+//! `dram`) becomes its own sensor, identified by the zone's `name` file.
 
-use crate::sensor::RAPL_PATH;
+use crate::sensor::{Component, EnergySensor, EnergyUnit};
 use std::error::Error;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 
-/// Refreshes and retrieves the current energy consumption from the Intel RAPL interface.
-///
-/// This function reads the energy consumption file from the Intel RAPL power monitoring
-/// subsystem, which tracks CPU energy usage in microjoules. It requires elevated privileges
-/// (sudo) to access the system file.
+/// Location of the powercap class, relative to the sysfs root.
+pub const POWERCAP_DIR: &str = "class/powercap";
+
+/// Prefix of RAPL zone directories in the powercap class.
+const RAPL_ZONE_PREFIX: &str = "intel-rapl:";
+
+/// A RAPL power zone found in sysfs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RaplDomain {
+    /// Zone directory name, e.g. `intel-rapl:0:1`.
+    pub zone: String,
+    /// Domain name made unique across sockets, e.g. `package-1` or `dram-0`.
+    pub name: String,
+    /// Path of the zone directory.
+    pub path: PathBuf,
+}
+
+impl RaplDomain {
+    /// Whether the domain belongs in the CPU total.
+    ///
+    /// Packages and DRAM are disjoint; `core`/`uncore` are already part of their package
+    /// and `psys` covers the whole SoC, so counting them would add energy twice.
+    pub fn counts_in_total(&self) -> bool {
+        self.name.starts_with("package") || self.name.starts_with("dram")
+    }
+}
+
+/// Finds every RAPL zone and subzone below `powercap_root`, sorted by zone name.
+pub fn discover_domains(powercap_root: &Path) -> Vec<RaplDomain> {
+    let Ok(entries) = fs::read_dir(powercap_root) else {
+        return Vec::new();
+    };
+
+    let mut domains: Vec<RaplDomain> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let zone = entry.file_name().to_string_lossy().into_owned();
+            let indices: Vec<&str> = zone.strip_prefix(RAPL_ZONE_PREFIX)?.split(':').collect();
+            if indices.len() > 2 || indices.iter().any(|i| i.parse::<u32>().is_err()) {
+                return None;
+            }
+
+            let path = entry.path();
+            let raw_name = fs::read_to_string(path.join("name")).ok()?;
+            let raw_name = raw_name.trim();
+            // Subzone names (core, dram, ...) repeat on every socket
+            let name = if indices.len() == 2 && !raw_name.contains('-') {
+                format!("{}-{}", raw_name, indices[0])
+            } else {
+                raw_name.to_string()
+            };
+            Some(RaplDomain { zone, name, path })
+        })
+        .collect();
+    domains.sort_by(|a, b| a.zone.cmp(&b.zone));
+    domains
+}
+
+/// RAPL energy counter of one domain, exposed as an [`EnergySensor`].
 ///
-/// # Returns
+/// The counter file is kept open and re-read from the start on every sample.
+pub struct RaplSensor {
+    domain: RaplDomain,
+    rapl_file: BufReader<File>,
+    /// Counter range from `max_energy_range_uj`; `energy_uj` wraps to 0 past it.
+    max_energy_uj: Option<f64>,
+}
+
+impl RaplSensor {
+    //sudo chmod +r /sys/class/powercap/intel-rapl/intel-rapl:0/energy_uj
+    pub fn open(domain: RaplDomain) -> Result<RaplSensor, Box<dyn Error>> {
+        let mut rapl_file = BufReader::new(File::open(domain.path.join("energy_uj"))?);
+        // Fail early if the counter is not readable (e.g. missing permissions)
+        get_energy(&mut rapl_file)?;
+        let max_energy_uj = fs::read_to_string(domain.path.join("max_energy_range_uj"))
+            .ok()
+            .and_then(|s| s.trim().parse::<f64>().ok());
+        Ok(RaplSensor {
+            domain,
+            rapl_file,
+            max_energy_uj,
+        })
+    }
+}
+
+impl EnergySensor for RaplSensor {
+    fn name(&self) -> String {
+        format!("rapl:{}", self.domain.name)
+    }
+    fn component(&self) -> Component {
+        Component::Cpu
+    }
+    fn unit(&self) -> EnergyUnit {
+        EnergyUnit::Microjoules
+    }
+    fn counts_in_total(&self) -> bool {
+        self.domain.counts_in_total()
+    }
+    fn max_energy(&self) -> Option<f64> {
+        self.max_energy_uj
+    }
+    fn read_energy(&mut self) -> Result<f64, Box<dyn Error>> {
+        get_energy(&mut self.rapl_file)
+    }
+}
+
+/// Reads the current value of an open `energy_uj` counter of a RAPL zone, in microjoules.
 ///
-/// `Result<usize, Box<dyn Error>>` - The energy consumption in microjoules, or an error
-/// if the file could not be read or parsed.
+/// The file is read again from the start, so one handle serves every sample. The counter
+/// is cumulative since boot and wraps around at the zone's `max_energy_range_uj`; the
+/// energy of an interval is the difference of two readings.
 ///
 /// # Errors
 ///
-/// Returns an error if:
-/// - The sudo command fails or returns non-zero status
-/// - The file cannot be read due to permission issues
-/// - The energy value cannot be parsed as a valid integer
-///
-/// # Example
-///
-/// ```no_run
-/// use sensor::energy::refresh_energy;
-///
-/// let energy = refresh_energy()?;
-/// println!("Energy consumed: {} microjoules", energy);
-/// # Ok::<(), Box<dyn std::error::Error>>(())
-/// ```
-///
-/// # Note
-///
-/// - This function requires the program to be run with sudo privileges
-/// - Energy values are cumulative and represent total consumption since system boot
-/// - Call this function periodically to calculate energy consumed during a time period
+/// Returns an error if the file cannot be read (e.g. without read permission, which
+/// recent kernels only grant to root) or its content is not a number.
 pub fn get_energy(rapl_file: &mut BufReader<File>) -> Result<f64, Box<dyn Error>> {
-    // Parse the energy consumption from the file (in microjoules)
-    //sudo chmod +r /sys/class/powercap/intel-rapl/intel-rapl:0/energy_uj
-
     rapl_file.seek(SeekFrom::Start(0))?;
 
     let mut buffer = String::new();
     rapl_file.read_line(&mut buffer)?;
 
     let energy_consumed = buffer.trim().parse::<f64>()?;
+    Ok(energy_consumed) //energy in microjoules
+}
 
-    // dbg!(energy_consumed);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::EnergySensor;
 
-    Ok(energy_consumed) //energy in microjoules
+    /// Creates a zone directory with the given files below `root`.
+    fn zone(root: &Path, zone: &str, files: &[(&str, &str)]) {
+        let dir = root.join(zone);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, content) in files {
+            fs::write(dir.join(name), content).unwrap();
+        }
+    }
+
+    #[test]
+    fn discovers_packages_and_subzones() {
+        let root = tempfile::tempdir().unwrap();
+        let range = ("max_energy_range_uj", "262143328850\n");
+        zone(
+            root.path(),
+            "intel-rapl:0",
+            &[("name", "package-0\n"), range],
+        );
+        zone(root.path(), "intel-rapl:0:0", &[("name", "core\n"), range]);
+        zone(root.path(), "intel-rapl:0:1", &[("name", "dram\n"), range]);
+        zone(
+            root.path(),
+            "intel-rapl:1",
+            &[("name", "package-1\n"), range],
+        );
+        zone(root.path(), "intel-rapl:1:0", &[("name", "dram\n"), range]);
+        // Not RAPL zones
+        zone(root.path(), "intel-rapl-mmio:0", &[("name", "package-0\n")]);
+        zone(root.path(), "intel-rapl:0:0:0", &[("name", "nested\n")]);
+
+        let domains = discover_domains(root.path());
+        let names: Vec<(&str, &str)> = domains
+            .iter()
+            .map(|d| (d.zone.as_str(), d.name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("intel-rapl:0", "package-0"),
+                ("intel-rapl:0:0", "core-0"),
+                ("intel-rapl:0:1", "dram-0"),
+                ("intel-rapl:1", "package-1"),
+                ("intel-rapl:1:0", "dram-1"),
+            ]
+        );
+        let in_total: Vec<bool> = domains.iter().map(|d| d.counts_in_total()).collect();
+        assert_eq!(in_total, [true, false, true, true, true]);
+    }
+
+    #[test]
+    fn missing_range_leaves_max_energy_unknown() {
+        let root = tempfile::tempdir().unwrap();
+        zone(
+            root.path(),
+            "intel-rapl:0",
+            &[("name", "package-0\n"), ("energy_uj", "1000\n")],
+        );
+
+        let domain = discover_domains(root.path()).remove(0);
+        let mut sensor = RaplSensor::open(domain).unwrap();
+        assert_eq!(sensor.max_energy(), None);
+        assert_eq!(sensor.read_energy().unwrap(), 1000.0);
+    }
+
+    #[test]
+    fn skips_unreadable_zones() {
+        let root = tempfile::tempdir().unwrap();
+        zone(
+            root.path(),
+            "intel-rapl:0",
+            &[("name", "package-0\n"), ("energy_uj", "1000\n")],
+        );
+        // No name: left out of discovery
+        zone(root.path(), "intel-rapl:1", &[("energy_uj", "1000\n")]);
+        // No counter: discovered, but cannot be opened
+        zone(root.path(), "intel-rapl:2", &[("name", "package-2\n")]);
+
+        let domains = discover_domains(root.path());
+        let zones: Vec<&str> = domains.iter().map(|d| d.zone.as_str()).collect();
+        assert_eq!(zones, ["intel-rapl:0", "intel-rapl:2"]);
+        assert!(RaplSensor::open(domains[0].clone()).is_ok());
+        assert!(RaplSensor::open(domains[1].clone()).is_err());
+        assert!(discover_domains(&root.path().join("missing")).is_empty());
+    }
 }
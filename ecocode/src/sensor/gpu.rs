@@ -3,37 +3,137 @@
 //! This module provides functionality to monitor GPU energy consumption and utilization
 //! using the NVIDIA Management Library (NVML) wrapper. It can retrieve both system-wide
 //! and per-process GPU metrics.
+//!
+//! The GPU is an optional backend: [`GpuSensor::detect`] returns `None` when no NVIDIA
+//! driver/device is present, or when EcoCode is built without the `nvml` feature, and
+//! the measurement loop then runs in CPU-only mode.
 
-use core::time;
+use crate::sensor::{Component, EnergySensor, EnergyUnit};
+use std::error::Error;
 
+#[cfg(feature = "nvml")]
+use crate::sensor::Capabilities;
+#[cfg(feature = "nvml")]
 use nvml_wrapper::{Device, Nvml};
+#[cfg(feature = "nvml")]
+use std::collections::HashMap;
 
 /// Default NVIDIA GPU device index to monitor.
 pub const DEFAULT_GPU_DEVICE_INDEX: u32 = 0;
 
-pub fn get_gpu_info() -> Result<(), Box<dyn std::error::Error>> {
-    let nvml = Nvml::init()?;
-    let device = nvml.device_by_index(DEFAULT_GPU_DEVICE_INDEX)?;
-    let name = device.name()?;
-    println!("\n[GPU INFO]");
-    println!("  GPU Name: {}", name);
-    Ok(())
+/// A detected NVML-backed GPU.
+///
+/// Holds the NVML handle so the library is only initialized once per run.
+#[cfg(feature = "nvml")]
+pub struct GpuSensor {
+    nvml: Nvml,
+    device_index: u32,
+    /// Timestamp (µs) of the newest utilization sample seen so far.
+    /// Initially 0, which targets all samples.
+    timestamp: u64,
 }
 
-pub fn get_gpu_power() -> Result<u32, Box<dyn std::error::Error>> {
-    let nvml = Nvml::init()?;
-    let device = nvml.device_by_index(DEFAULT_GPU_DEVICE_INDEX)?;
-    let power = device.power_usage()?; // in mW
-
-    Ok(power)
+/// Without the `nvml` feature no GPU can ever be detected.
+#[cfg(not(feature = "nvml"))]
+pub enum GpuSensor {}
+
+#[cfg(feature = "nvml")]
+impl GpuSensor {
+    /// Initializes NVML and opens the device at `device_index`.
+    ///
+    /// Returns `None` (after printing the reason to stderr) if NVML is unavailable,
+    /// so that hosts without an NVIDIA GPU fall back to CPU-only measurement.
+    pub fn detect(device_index: u32) -> Option<GpuSensor> {
+        let nvml = match Nvml::init() {
+            Ok(n) => n,
+            Err(e) => {
+                eprintln!("NVML unavailable ({}), GPU monitoring disabled.", e);
+                return None;
+            }
+        };
+        match nvml.device_by_index(device_index) {
+            Ok(device) => {
+                eprintln!("\n[GPU INFO]");
+                eprintln!("  GPU Name: {}", device.name().unwrap_or_default());
+            }
+            Err(e) => {
+                eprintln!(
+                    "GPU device {} unavailable ({}), GPU monitoring disabled.",
+                    device_index, e
+                );
+                return None;
+            }
+        }
+        Some(GpuSensor {
+            nvml,
+            device_index,
+            timestamp: 0,
+        })
+    }
+
+    fn device(&self) -> Result<Device<'_>, Box<dyn Error>> {
+        Ok(self.nvml.device_by_index(self.device_index)?)
+    }
 }
 
-pub fn get_gpu_energy(device: &Device) -> Result<f64, Box<dyn std::error::Error>> {
-    //optimize: use nvml as input since reinit is expensive
+#[cfg(feature = "nvml")]
+impl EnergySensor for GpuSensor {
+    fn name(&self) -> String {
+        format!("nvml:{}", self.device_index)
+    }
+    fn component(&self) -> Component {
+        Component::Gpu
+    }
+    fn unit(&self) -> EnergyUnit {
+        EnergyUnit::Millijoules
+    }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { per_process: true }
+    }
+
+    /// Total energy consumed by the device since the driver was loaded, in millijoules.
+    fn read_energy(&mut self) -> Result<f64, Box<dyn Error>> {
+        let energy_milij = self.device()?.total_energy_consumption()?; // in mJ
+
+        Ok(energy_milij as f64)
+    }
+
+    fn process_usage(&mut self, pids: &[u32]) -> Option<HashMap<u32, f64>> {
+        let device = match self.device() {
+            Ok(d) => d,
+            Err(e) => {
+                eprintln!("Error getting GPU device: {}", e);
+                return Some(HashMap::new());
+            }
+        };
+        let (usage, next_timestamp) = get_gpu_usage_by_pids(&device, pids, self.timestamp);
+        self.timestamp = next_timestamp;
+        Some(usage)
+    }
+}
 
-    let energy_milij = device.total_energy_consumption()?; // in mJ
+#[cfg(not(feature = "nvml"))]
+impl GpuSensor {
+    pub fn detect(_device_index: u32) -> Option<GpuSensor> {
+        eprintln!("Built without the `nvml` feature, GPU monitoring disabled.");
+        None
+    }
+}
 
-    Ok(energy_milij as f64)
+#[cfg(not(feature = "nvml"))]
+impl EnergySensor for GpuSensor {
+    fn name(&self) -> String {
+        match *self {}
+    }
+    fn component(&self) -> Component {
+        match *self {}
+    }
+    fn unit(&self) -> EnergyUnit {
+        match *self {}
+    }
+    fn read_energy(&mut self) -> Result<f64, Box<dyn Error>> {
+        match *self {}
+    }
 }
 
 // 1. What are these samples?
@@ -50,27 +150,30 @@ pub fn get_gpu_energy(device: &Device) -> Result<f64, Box<dyn std::error::Error>
 // mem_util: Video memory utilization during that slice.
 // enc_util / dec_util: Video encoder/decoder activity.
 
-pub fn get_gpu_energy_by_pid(
+/// Average utilization (0-100 %) of the device by each of `pids` across all driver
+/// samples newer than `timestamp`, together with the timestamp to pass on the next call.
+/// PIDs without samples are left out of the map.
+#[cfg(feature = "nvml")]
+pub fn get_gpu_usage_by_pids(
     device: &Device,
-    pid: u32,
-    energy_1: f64,
-    energy_2: f64,
+    pids: &[u32],
     timestamp: u64,
-    interval_secs: f64,
-) -> (f64, f64, u64) {
+) -> (HashMap<u32, f64>, u64) {
     let stats = match device.process_utilization_stats(timestamp) {
         Ok(s) => s,
         Err(e) => {
-            eprintln!("Error fetching process utilization for PID {}: {}, maybe it stopped running on the GPU.", pid, e);
-            return (0.0, 0.0, timestamp); // Return current timestamp to retry next time
+            eprintln!(
+                "Error fetching process utilization for PIDs {:?}: {}, maybe they stopped running on the GPU.",
+                pids, e
+            );
+            return (HashMap::new(), timestamp); // Return current timestamp to retry next time
         }
     };
 
     //If the function fails once and we return the same timestamp, the next successful call will return samples for a 2-second window (the failed 1s + the current 1s).
 
-    // However, in
-    // main.rs
-    // we are still calculating total_gpu_power_w using only the 1-second delta of the physical hardware energy counters (energy_2 - energy_1).
+    // However, the sensor registry still calculates the total GPU power using only the
+    // 1-second delta of the physical hardware energy counters (energy_2 - energy_1).
 
     // The result: You are applying the average utilization of the last 2 seconds to the total power consumed in the last 1 second. It's an approximation that says: "I don't know exactly what happened in the last second, so I'll use the average of everything I've seen since my last successful check."
 
@@ -85,34 +188,19 @@ pub fn get_gpu_energy_by_pid(
 
     // println!("{:?}", stats);
 
-    // Filter samples for this PID
-    let pid_samples: Vec<_> = stats.iter().filter(|s| s.pid == pid).collect();
-
-    let process_util = if pid_samples.is_empty() {
-        0.0
-    } else {
-        // Average the utilization across all samples in this period
-        let sum: u64 = pid_samples.iter().map(|s| s.sm_util as u64).sum();
-        (sum as f64 / pid_samples.len() as f64) / 100.0
-    };
-
-
-    let delta_energy_mj = if energy_2 >= energy_1 {
-        energy_2 - energy_1
-    } else {
-        // Handle wraparound. NVML total energy is usually 64-bit, but some drivers
-        // might report 32-bit values or reset.
-        println!("Warning: GPU energy counter wrapped or reset.");
-        energy_2 // Minimum delta we can assume is the new value
-    };
-//The code detects 200 < 1,000,000 and enters the else block:
-
-// Delta: 200 mJ (It assumes the 200 mJ used since the reset is the safest delta).
-// Power: 200 / 1000 / 1.0 = 0.2 Watts.
-// EcoCode UI: Displays a very low power usage for that single second, keeping your data clean and physically accurate.
-
-    let total_gpu_power_w = delta_energy_mj / 1000.0 / interval_secs;
-    let process_gpu_power_w = total_gpu_power_w * process_util;
-
-    (process_gpu_power_w, process_util * 100.0, next_timestamp)
+    // Group the samples of the target PIDs: (sum of sm_util, sample count)
+    let mut per_pid: HashMap<u32, (u64, u64)> = HashMap::new();
+    for sample in stats.iter().filter(|s| pids.contains(&s.pid)) {
+        let entry = per_pid.entry(sample.pid).or_default();
+        entry.0 += sample.sm_util as u64;
+        entry.1 += 1;
+    }
+
+    // Average the utilization across all samples of each PID in this period
+    let process_util = per_pid
+        .into_iter()
+        .map(|(pid, (sum, count))| (pid, sum as f64 / count as f64))
+        .collect();
+
+    (process_util, next_timestamp)
 }
@@ -0,0 +1,96 @@
+//! Energy monitoring through the Linux hwmon subsystem.
+//!
+//! Some drivers (e.g. `amd_energy`, `zenpower`, ACPI power meters) expose cumulative
+//! energy counters as `/sys/class/hwmon/hwmonN/energyM_input`, in microjoules.
+
+use crate::sensor::{Component, EnergySensor, EnergyUnit};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Location of the hwmon class, relative to the sysfs root.
+pub const HWMON_DIR: &str = "class/hwmon";
+
+/// hwmon drivers whose energy channels measure the CPU.
+const CPU_DRIVERS: &[&str] = &["amd_energy", "zenpower"];
+
+/// A single `energyN_input` channel of a hwmon device.
+pub struct HwmonSensor {
+    path: PathBuf,
+    driver: String,
+    label: String,
+    component: Component,
+}
+
+impl HwmonSensor {
+    /// Finds every readable energy channel below `hwmon_root`.
+    ///
+    /// For CPU drivers only the per-socket channels are kept, since the per-core ones
+    /// are already included in them.
+    pub fn discover(hwmon_root: &Path) -> Vec<HwmonSensor> {
+        let Ok(devices) = fs::read_dir(hwmon_root) else {
+            return Vec::new();
+        };
+
+        let mut sensors = Vec::new();
+        for device in devices.flatten() {
+            let dir = device.path();
+            let driver = read_trimmed(&dir.join("name")).unwrap_or_default();
+            let component = if CPU_DRIVERS.contains(&driver.as_str()) {
+                Component::Cpu
+            } else {
+                Component::System
+            };
+
+            let Ok(files) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for file in files.flatten() {
+                let file_name = file.file_name().to_string_lossy().into_owned();
+                let Some(channel) = file_name
+                    .strip_prefix("energy")
+                    .and_then(|f| f.strip_suffix("_input"))
+                else {
+                    continue;
+                };
+
+                let label = read_trimmed(&dir.join(format!("energy{}_label", channel)))
+                    .unwrap_or_else(|| format!("energy{}", channel));
+                if component == Component::Cpu && !label.to_lowercase().contains("socket") {
+                    continue;
+                }
+
+                let path = file.path();
+                if read_trimmed(&path).is_none() {
+                    continue;
+                }
+                sensors.push(HwmonSensor {
+                    path,
+                    driver: driver.clone(),
+                    label,
+                    component,
+                });
+            }
+        }
+        sensors
+    }
+}
+
+impl EnergySensor for HwmonSensor {
+    fn name(&self) -> String {
+        format!("hwmon:{}:{}", self.driver, self.label)
+    }
+    fn component(&self) -> Component {
+        self.component
+    }
+    fn unit(&self) -> EnergyUnit {
+        EnergyUnit::Microjoules
+    }
+    fn read_energy(&mut self) -> Result<f64, Box<dyn Error>> {
+        Ok(fs::read_to_string(&self.path)?.trim().parse::<f64>()?)
+    }
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
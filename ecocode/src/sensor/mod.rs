@@ -1,11 +1,372 @@
 //! Sensor module for monitoring system energy consumption.
 //!
-//! This module provides functions to read CPU and GPU energy data from the system.
-//! It includes:
-//! - `energy`: CPU energy monitoring using Intel RAPL interface
-//! - `gpu`: GPU energy monitoring using NVIDIA Management Library (NVML)
+//! Every power source is a backend implementing [`EnergySensor`], which exposes a
+//! cumulative energy counter. The [`SensorRegistry`] probes all backends at startup and
+//! hands the sampling loop a uniform list of sensors, so adding a new power source does
+//! not require touching `main()`.
+//!
+//! Backends:
+//! - `cpu`: CPU energy monitoring using Intel RAPL interface
+//! - `gpu`: GPU energy monitoring using NVIDIA Management Library (NVML), optional at
+//!   runtime and behind the `nvml` cargo feature at compile time
+//! - `hwmon`: energy channels exposed by Linux hwmon drivers (e.g. `amd_energy`)
 
 pub mod cpu;
 pub mod gpu;
+pub mod hwmon;
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::path::Path;
+
+/// Default sysfs mount point. Overridable so discovery can run against a fake tree.
+pub const SYSFS_ROOT: &str = "/sys";
+
+/// Hardware component a sensor measures; determines how its power is attributed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Component {
+    Cpu,
+    Gpu,
+    System,
+}
+
+/// Unit of the cumulative counter returned by [`EnergySensor::read_energy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnergyUnit {
+    Microjoules,
+    #[cfg_attr(not(feature = "nvml"), allow(dead_code))]
+    Millijoules,
+}
+
+impl EnergyUnit {
+    /// Converts a counter value expressed in this unit to joules.
+    pub fn to_joules(self, value: f64) -> f64 {
+        match self {
+            EnergyUnit::Microjoules => value / 1_000_000.0,
+            EnergyUnit::Millijoules => value / 1_000.0,
+        }
+    }
+}
+
+/// Optional features a backend supports beyond its energy counter.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Capabilities {
+    /// The backend can tell how much of the device a given process used
+    /// (see [`EnergySensor::process_usage`]).
+    pub per_process: bool,
+}
+
+/// A source of cumulative energy readings.
+pub trait EnergySensor {
+    /// Human readable name, e.g. `rapl:package-0` or `nvml:0`.
+    fn name(&self) -> String;
+    fn component(&self) -> Component;
+    fn unit(&self) -> EnergyUnit;
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+    /// Whether this sensor's energy is disjoint from the other sensors of the same
+    /// component and belongs in the component total. Sub-domains that overlap another
+    /// sensor (e.g. RAPL `core` inside `package`) return `false`.
+    fn counts_in_total(&self) -> bool {
+        true
+    }
+    /// Value at which the counter wraps back to zero, in [`EnergySensor::unit`], if
+    /// the backend publishes it (RAPL `max_energy_range_uj`).
+    fn max_energy(&self) -> Option<f64> {
+        None
+    }
+    /// Reads the cumulative energy counter, expressed in [`EnergySensor::unit`].
+    fn read_energy(&mut self) -> Result<f64, Box<dyn Error>>;
+    /// Percentage (0-100) of the device used by each of `pids` since the previous call.
+    /// Processes that did not use the device may be missing from the map.
+    ///
+    /// Only meaningful when [`Capabilities::per_process`] is set.
+    fn process_usage(&mut self, _pids: &[u32]) -> Option<HashMap<u32, f64>> {
+        None
+    }
+}
+
+/// Average power of one sensor over a sampling interval.
+#[derive(Clone, Debug)]
+pub struct SensorSample {
+    pub name: String,
+    pub component: Component,
+    pub counts_in_total: bool,
+    /// The counter went backwards during the interval (wraparound or reset).
+    pub wrapped: bool,
+    /// Energy consumed during the interval, from the raw counter delta.
+    pub energy_j: f64,
+    pub power_w: f64,
+    /// Share of the device used by each target process, if the backend knows it.
+    pub process_usage: Option<HashMap<u32, f64>>,
+}
+
+struct TrackedSensor {
+    sensor: Box<dyn EnergySensor>,
+    last_energy: f64,
+}
+
+/// The set of sensors available on this host.
+pub struct SensorRegistry {
+    sensors: Vec<TrackedSensor>,
+}
+
+impl SensorRegistry {
+    /// Probes every known backend and keeps those that are available.
+    ///
+    /// hwmon CPU channels are only used when RAPL is unavailable, since on AMD parts
+    /// both interfaces report the same energy.
+    pub fn probe(sysfs_root: &Path) -> SensorRegistry {
+        let mut sensors: Vec<Box<dyn EnergySensor>> = Vec::new();
+
+        let powercap_root = sysfs_root.join(cpu::POWERCAP_DIR);
+        let domains = cpu::discover_domains(&powercap_root);
+        if domains.is_empty() {
+            eprintln!(
+                "RAPL unavailable: no zones found in {}",
+                powercap_root.display()
+            );
+        }
+        for domain in domains {
+            let zone = domain.zone.clone();
+            match cpu::RaplSensor::open(domain) {
+                Ok(s) => sensors.push(Box::new(s)),
+                Err(e) => eprintln!("RAPL zone {} unavailable: {}", zone, e),
+            }
+        }
+
+        let has_cpu = !sensors.is_empty();
+        for s in hwmon::HwmonSensor::discover(&sysfs_root.join(hwmon::HWMON_DIR)) {
+            if has_cpu && s.component() == Component::Cpu {
+                continue;
+            }
+            sensors.push(Box::new(s));
+        }
+
+        if let Some(s) = gpu::GpuSensor::detect(gpu::DEFAULT_GPU_DEVICE_INDEX) {
+            sensors.push(Box::new(s));
+        }
+
+        SensorRegistry::from_sensors(sensors)
+    }
+
+    pub fn from_sensors(sensors: Vec<Box<dyn EnergySensor>>) -> SensorRegistry {
+        SensorRegistry {
+            sensors: sensors
+                .into_iter()
+                .map(|sensor| TrackedSensor {
+                    sensor,
+                    last_energy: 0.0,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn has(&self, component: Component) -> bool {
+        self.sensors
+            .iter()
+            .any(|t| t.sensor.component() == component)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.sensors.iter().map(|t| t.sensor.name()).collect()
+    }
+
+    /// Takes the initial reading of every sensor.
+    pub fn start(&mut self) -> Result<(), Box<dyn Error>> {
+        for t in &mut self.sensors {
+            t.last_energy = t.sensor.read_energy()?;
+        }
+        Ok(())
+    }
+
+    /// Reads every sensor and returns its average power since the previous reading.
+    ///
+    /// The end-of-interval reading becomes the start of the next interval.
+    pub fn sample(
+        &mut self,
+        pids: &[u32],
+        elapsed_secs: f64,
+    ) -> Result<Vec<SensorSample>, Box<dyn Error>> {
+        let mut samples = Vec::with_capacity(self.sensors.len());
+        for t in &mut self.sensors {
+            let energy = t.sensor.read_energy()?;
+            let (delta, wrapped) = counter_delta(t.last_energy, energy, t.sensor.max_energy())
+                .unwrap_or_else(|| {
+                    // The counter was reset (e.g. a driver reload) and the energy of this
+                    // interval is unknown; counting none keeps the data physically
+                    // plausible instead of showing a huge or negative power.
+                    eprintln!(
+                        "Warning: {} energy counter wrapped or reset.",
+                        t.sensor.name()
+                    );
+                    (0.0, true)
+                });
+            t.last_energy = energy;
+
+            let process_usage = if t.sensor.capabilities().per_process {
+                t.sensor.process_usage(pids)
+            } else {
+                None
+            };
+
+            let energy_j = t.sensor.unit().to_joules(delta);
+            samples.push(SensorSample {
+                name: t.sensor.name(),
+                component: t.sensor.component(),
+                counts_in_total: t.sensor.counts_in_total(),
+                energy_j,
+                power_w: energy_j / elapsed_secs,
+                process_usage,
+                wrapped,
+            });
+        }
+        Ok(samples)
+    }
+}
+
+/// Difference between two cumulative counter readings, and whether the counter went
+/// backwards in between.
+///
+/// With a known `max_energy` a backwards step is a wraparound: the counter ran up to
+/// `max_energy`, restarted at zero and counted up to `energy_2`. Without one the
+/// difference cannot be known and `None` is returned.
+fn counter_delta(energy_1: f64, energy_2: f64, max_energy: Option<f64>) -> Option<(f64, bool)> {
+    if energy_2 >= energy_1 {
+        return Some((energy_2 - energy_1, false));
+    }
+    match max_energy {
+        Some(max) if max >= energy_1 => Some((max - energy_1 + energy_2, true)),
+        _ => None,
+    }
+}
+
+/// The set of processes power is attributed to, e.g. a whole process tree or a single
+/// member of it.
+pub struct Attribution<'a> {
+    pub pids: &'a [u32],
+    /// Share of the whole CPU (0-1) used by `pids` during the interval.
+    pub cpu_share: f64,
+}
+
+impl SensorSample {
+    /// Share (0-100 %) of the device used by `pids`, if the backend reports it.
+    pub fn usage_of(&self, pids: &[u32]) -> Option<f64> {
+        let usage = self.process_usage.as_ref()?;
+        let total: f64 = pids.iter().filter_map(|pid| usage.get(pid)).sum();
+        Some(total.min(100.0))
+    }
+
+    /// Share (0-1) of this sensor's energy that belongs to the target processes.
+    ///
+    /// Devices that report per-process usage (GPUs) are split by that usage, everything
+    /// else by the processes' share of the CPU.
+    fn share_of(&self, target: &Attribution) -> f64 {
+        match self.usage_of(target.pids) {
+            Some(usage) => usage / 100.0,
+            None => target.cpu_share,
+        }
+    }
+
+    /// Power attributed to the target processes.
+    pub fn attributed_power(&self, target: &Attribution) -> f64 {
+        self.power_w * self.share_of(target)
+    }
+
+    /// Energy attributed to the target processes.
+    pub fn attributed_energy(&self, target: &Attribution) -> f64 {
+        self.energy_j * self.share_of(target)
+    }
+}
+
+/// Sum of the power attributed to the target by all sensors of `component`, or `None`
+/// if no such sensor exists.
+///
+/// Sensors that overlap others (see [`EnergySensor::counts_in_total`]) are left out.
+pub fn attributed_power(
+    samples: &[SensorSample],
+    component: Component,
+    target: &Attribution,
+) -> Option<f64> {
+    let mut matching = samples
+        .iter()
+        .filter(|s| s.component == component && s.counts_in_total)
+        .peekable();
+    matching.peek()?;
+    Some(matching.map(|s| s.attributed_power(target)).sum())
+}
+
+/// Sum of the energy attributed to the target by all sensors of `component`, or `None`
+/// if no such sensor exists. Counts the same sensors as [`attributed_power`].
+pub fn attributed_energy(
+    samples: &[SensorSample],
+    component: Component,
+    target: &Attribution,
+) -> Option<f64> {
+    let mut matching = samples
+        .iter()
+        .filter(|s| s.component == component && s.counts_in_total)
+        .peekable();
+    matching.peek()?;
+    Some(matching.map(|s| s.attributed_energy(target)).sum())
+}
+
+/// Power attributed to the target by each sensor of `component`, keyed by sensor name.
+///
+/// Includes overlapping sub-domains, so the values do not necessarily add up to the
+/// [`attributed_power`] total.
+pub fn attributed_domains(
+    samples: &[SensorSample],
+    component: Component,
+    target: &Attribution,
+) -> BTreeMap<String, f64> {
+    samples
+        .iter()
+        .filter(|s| s.component == component)
+        .map(|s| (s.name.clone(), s.attributed_power(target)))
+        .collect()
+}
+
+/// Mean usage of the `component` devices by `pids`, if any device reports it.
+pub fn process_usage(samples: &[SensorSample], component: Component, pids: &[u32]) -> Option<f64> {
+    let usages: Vec<f64> = samples
+        .iter()
+        .filter(|s| s.component == component)
+        .filter_map(|s| s.usage_of(pids))
+        .collect();
+    if usages.is_empty() {
+        return None;
+    }
+    Some(usages.iter().sum::<f64>() / usages.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_delta_of_increasing_readings() {
+        assert_eq!(
+            counter_delta(1_000.0, 4_500.0, Some(10_000.0)),
+            Some((3_500.0, false))
+        );
+        assert_eq!(counter_delta(1_000.0, 1_000.0, None), Some((0.0, false)));
+    }
+
+    #[test]
+    fn counter_delta_across_a_wrap() {
+        // 9,000 -> 10,000 (the range), then 0 -> 500
+        assert_eq!(
+            counter_delta(9_000.0, 500.0, Some(10_000.0)),
+            Some((1_500.0, true))
+        );
+    }
 
-pub const RAPL_PATH: &str = "/sys/class/powercap/intel-rapl/intel-rapl:0/energy_uj";
+    #[test]
+    fn counter_delta_without_a_range() {
+        assert_eq!(counter_delta(9_000.0, 500.0, None), None);
+        // A reading beyond the reported range is not a wrap either
+        assert_eq!(counter_delta(20_000.0, 500.0, Some(10_000.0)), None);
+    }
+}
@@ -0,0 +1,65 @@
+//! Graceful shutdown on SIGINT and SIGTERM.
+//!
+//! Instead of killing EcoCode, a signal is recorded and picked up by the measurement
+//! loop, which finishes the current sample and lets every exporter write its final
+//! output before exiting.
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::SignalsInfo;
+use signal_hook::iterator::exfiltrator::WithOrigin;
+use signal_hook::low_level::siginfo::Cause;
+
+/// A received termination signal.
+#[derive(Clone, Copy, Debug)]
+pub struct Request {
+    pub signal: i32,
+    /// Generated by the kernel, e.g. Ctrl-C on the terminal: it went to the whole
+    /// foreground process group, so a spawned command received it too.
+    pub from_terminal: bool,
+}
+
+impl Request {
+    pub fn signal_name(&self) -> &'static str {
+        match self.signal {
+            SIGINT => "SIGINT",
+            SIGTERM => "SIGTERM",
+            _ => "signal",
+        }
+    }
+}
+
+/// Receives SIGINT and SIGTERM on a background thread.
+pub struct Shutdown {
+    pending: Arc<Mutex<Option<Request>>>,
+}
+
+impl Shutdown {
+    pub fn install() -> io::Result<Shutdown> {
+        let mut signals = SignalsInfo::<WithOrigin>::new([SIGINT, SIGTERM])?;
+        let pending = Arc::new(Mutex::new(None));
+        let sink = Arc::clone(&pending);
+        thread::spawn(move || {
+            for origin in signals.forever() {
+                *sink.lock().unwrap() = Some(Request {
+                    signal: origin.signal,
+                    from_terminal: matches!(origin.cause, Cause::Kernel),
+                });
+            }
+        });
+        Ok(Shutdown { pending })
+    }
+
+    /// Whether a signal arrived that has not been taken yet.
+    pub fn requested(&self) -> bool {
+        self.pending.lock().unwrap().is_some()
+    }
+
+    /// Takes the latest pending signal.
+    pub fn take(&self) -> Option<Request> {
+        self.pending.lock().unwrap().take()
+    }
+}
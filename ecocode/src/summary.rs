@@ -0,0 +1,325 @@
+//! End-of-run energy summary.
+//!
+//! Energy is integrated from the raw counter deltas of every sample (see
+//! [`SensorSample::energy_j`]) instead of multiplying the average power by the nominal
+//! interval, so late wake-ups and the partial last sample are accounted exactly.
+
+use crate::exporter::Record;
+use crate::sensor::{Attribution, Component, SensorSample, attributed_energy};
+use serde::{Deserialize, Serialize};
+
+const JOULES_PER_WH: f64 = 3600.0;
+
+/// An amount of energy, in both joules and watt-hours.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Energy {
+    pub joules: f64,
+    pub wh: f64,
+}
+
+impl Energy {
+    fn from_joules(joules: f64) -> Energy {
+        Energy {
+            joules,
+            wh: joules / JOULES_PER_WH,
+        }
+    }
+}
+
+/// Emissions of the energy a run drew from the grid.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Carbon {
+    pub grams: f64,
+    /// Mean grid intensity over the run, weighted by energy, in gCO₂e/kWh.
+    pub intensity_g_per_kwh: f64,
+}
+
+impl Carbon {
+    /// Emissions of `grams` for the grid energy `total`.
+    pub fn new(grams: f64, total: Energy) -> Carbon {
+        let kwh = total.wh / 1000.0;
+        Carbon {
+            grams,
+            intensity_g_per_kwh: if kwh > 0.0 { grams / kwh } else { 0.0 },
+        }
+    }
+}
+
+/// Cost of the energy a run drew from the grid.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cost {
+    pub amount: f64,
+    pub currency: String,
+    /// Mean price over the run, weighted by energy, per kWh.
+    pub price_per_kwh: f64,
+}
+
+impl Cost {
+    /// Cost of `amount` for the grid energy `total`.
+    pub fn new(amount: f64, currency: &str, total: Energy) -> Cost {
+        let kwh = total.wh / 1000.0;
+        Cost {
+            amount,
+            currency: currency.to_string(),
+            price_per_kwh: if kwh > 0.0 { amount / kwh } else { 0.0 },
+        }
+    }
+}
+
+/// Water consumed for the energy of a run.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Water {
+    pub litres: f64,
+    /// On-site and off-site water per kWh drawn from the grid.
+    pub l_per_kwh: f64,
+}
+
+impl Water {
+    /// Water of `litres` for the grid energy `total`.
+    pub fn new(litres: f64, total: Energy) -> Water {
+        let kwh = total.wh / 1000.0;
+        Water {
+            litres,
+            l_per_kwh: if kwh > 0.0 { litres / kwh } else { 0.0 },
+        }
+    }
+}
+
+/// Totals of a whole run, attributed to the target.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Summary {
+    pub pid: u32,
+    /// Exit code of a spawned command, 0 when attached to running processes. None when
+    /// the measurement failed before a spawned command exited.
+    pub exit_code: Option<i32>,
+    /// Why the measurement failed, None for a run that ended normally and in older
+    /// recordings.
+    #[serde(default)]
+    pub error: Option<String>,
+    pub duration_secs: f64,
+    pub samples: usize,
+    pub cpu_energy: Energy,
+    pub gpu_energy: Option<Energy>,    // None without a GPU
+    pub system_energy: Option<Energy>, // None without a system-level sensor
+    /// CPU + GPU. System-level sensors usually include both, so they are left out.
+    pub total_energy: Energy,
+    /// Estimated energy drawn at the wall for the target: CPU + GPU plus its share of
+    /// the baseline, over the PSU efficiency. None without a wall-power conversion.
+    #[serde(default)]
+    pub wall_energy: Option<Energy>,
+    /// Estimated wall energy times the PUE. None without a wall-power conversion.
+    #[serde(default)]
+    pub facility_energy: Option<Energy>,
+    pub mean_power_w: f64,
+    pub peak_power_w: f64,
+    pub p95_power_w: f64,
+    /// None without `--carbon-intensity` and in older recordings.
+    #[serde(default)]
+    pub carbon: Option<Carbon>,
+    /// None without `--energy-price` and in older recordings.
+    #[serde(default)]
+    pub cost: Option<Cost>,
+    /// None without `--wue` or `--water-intensity` and in older recordings.
+    #[serde(default)]
+    pub water: Option<Water>,
+}
+
+impl Summary {
+    /// The summary as (name, value) pairs, for exporters without nested structures.
+    /// Absent components are left out.
+    pub fn to_rows(&self) -> Vec<(String, String)> {
+        let mut rows = vec![("pid".to_string(), self.pid.to_string())];
+        if let Some(exit_code) = self.exit_code {
+            rows.push(("exit_code".to_string(), exit_code.to_string()));
+        }
+        if let Some(error) = &self.error {
+            rows.push(("error".to_string(), error.clone()));
+        }
+        rows.extend([
+            ("duration_s".to_string(), self.duration_secs.to_string()),
+            ("samples".to_string(), self.samples.to_string()),
+        ]);
+        let components = [
+            ("cpu", Some(self.cpu_energy)),
+            ("gpu", self.gpu_energy),
+            ("system", self.system_energy),
+            ("total", Some(self.total_energy)),
+            ("wall", self.wall_energy),
+            ("facility", self.facility_energy),
+        ];
+        for (name, energy) in components {
+            if let Some(energy) = energy {
+                rows.push((format!("{}_energy_j", name), energy.joules.to_string()));
+                rows.push((format!("{}_energy_wh", name), energy.wh.to_string()));
+            }
+        }
+        rows.extend([
+            ("mean_power_w".to_string(), self.mean_power_w.to_string()),
+            ("peak_power_w".to_string(), self.peak_power_w.to_string()),
+            ("p95_power_w".to_string(), self.p95_power_w.to_string()),
+        ]);
+        if let Some(carbon) = self.carbon {
+            rows.push(("carbon_g".to_string(), carbon.grams.to_string()));
+            rows.push((
+                "carbon_intensity_g_per_kwh".to_string(),
+                carbon.intensity_g_per_kwh.to_string(),
+            ));
+        }
+        if let Some(cost) = &self.cost {
+            rows.push(("cost".to_string(), cost.amount.to_string()));
+            rows.push(("cost_currency".to_string(), cost.currency.clone()));
+            rows.push((
+                "cost_price_per_kwh".to_string(),
+                cost.price_per_kwh.to_string(),
+            ));
+        }
+        if let Some(water) = self.water {
+            rows.push(("water_l".to_string(), water.litres.to_string()));
+            rows.push(("water_l_per_kwh".to_string(), water.l_per_kwh.to_string()));
+        }
+        rows
+    }
+}
+
+/// Accumulates the energy attributed to the target over the samples of a run.
+#[derive(Default)]
+pub struct EnergyMeter {
+    duration_secs: f64,
+    cpu_j: f64,
+    gpu_j: Option<f64>,
+    system_j: Option<f64>,
+    wall_j: Option<f64>,
+    facility_j: Option<f64>,
+    carbon_g: Option<f64>,
+    cost: Option<f64>,
+    water_l: Option<f64>,
+    /// Currency of the sample costs.
+    currency: String,
+    /// Total (CPU + GPU) power of every sample, in watts.
+    powers: Vec<f64>,
+}
+
+impl EnergyMeter {
+    pub fn new() -> EnergyMeter {
+        EnergyMeter::default()
+    }
+
+    /// A meter whose sample costs are in `currency`.
+    pub fn with_currency(currency: Option<&str>) -> EnergyMeter {
+        EnergyMeter {
+            currency: currency.unwrap_or_default().to_string(),
+            ..EnergyMeter::default()
+        }
+    }
+
+    /// Adds one sample of `elapsed_secs` for the processes of `target`.
+    pub fn add(&mut self, samples: &[SensorSample], target: &Attribution, elapsed_secs: f64) {
+        let cpu = attributed_energy(samples, Component::Cpu, target).unwrap_or(0.0);
+        let gpu = attributed_energy(samples, Component::Gpu, target);
+        let system = attributed_energy(samples, Component::System, target);
+
+        self.duration_secs += elapsed_secs;
+        self.cpu_j += cpu;
+        if let Some(gpu) = gpu {
+            *self.gpu_j.get_or_insert(0.0) += gpu;
+        }
+        if let Some(system) = system {
+            *self.system_j.get_or_insert(0.0) += system;
+        }
+        if elapsed_secs > 0.0 {
+            self.powers.push((cpu + gpu.unwrap_or(0.0)) / elapsed_secs);
+        }
+    }
+
+    /// Adds the wall and facility energy, emissions, cost and water of a sample of the whole
+    /// target, as far as they were estimated for its record.
+    pub fn add_footprint(&mut self, record: &Record) {
+        let estimates = [
+            (&mut self.wall_j, record.wall_energy_j()),
+            (&mut self.facility_j, record.facility_energy_j()),
+            (&mut self.carbon_g, record.carbon_g),
+            (&mut self.cost, record.cost),
+            (&mut self.water_l, record.water_l),
+        ];
+        for (total, value) in estimates {
+            if let Some(value) = value {
+                *total.get_or_insert(0.0) += value;
+            }
+        }
+    }
+
+    /// Adds a recorded sample of the whole target, e.g. when replaying a run.
+    pub fn add_record(&mut self, record: &Record) {
+        let elapsed_secs = record.interval_secs;
+        let cpu = record.joules(record.cpu_power_w);
+        let gpu = record.gpu_power_w.map(|w| record.joules(w));
+
+        self.duration_secs += elapsed_secs;
+        self.cpu_j += cpu;
+        if let Some(gpu) = gpu {
+            *self.gpu_j.get_or_insert(0.0) += gpu;
+        }
+        if let Some(system) = record.system_power_w {
+            *self.system_j.get_or_insert(0.0) += record.joules(system);
+        }
+        if elapsed_secs > 0.0 {
+            self.powers.push((cpu + gpu.unwrap_or(0.0)) / elapsed_secs);
+        }
+        self.add_footprint(record);
+    }
+
+    pub fn summary(&self, pid: u32, exit_code: i32) -> Summary {
+        let total_j = self.cpu_j + self.gpu_j.unwrap_or(0.0);
+        let mean_power_w = if self.duration_secs > 0.0 {
+            total_j / self.duration_secs
+        } else {
+            0.0
+        };
+        let total_energy = Energy::from_joules(total_j);
+        let facility_energy = self.facility_j.map(Energy::from_joules);
+        // Emissions, costs and water are those of the energy drawn from the grid
+        let grid_energy = facility_energy.unwrap_or(total_energy);
+        Summary {
+            pid,
+            exit_code: Some(exit_code),
+            error: None,
+            duration_secs: self.duration_secs,
+            samples: self.powers.len(),
+            cpu_energy: Energy::from_joules(self.cpu_j),
+            gpu_energy: self.gpu_j.map(Energy::from_joules),
+            system_energy: self.system_j.map(Energy::from_joules),
+            total_energy,
+            wall_energy: self.wall_j.map(Energy::from_joules),
+            facility_energy,
+            mean_power_w,
+            peak_power_w: self.powers.iter().copied().fold(0.0, f64::max),
+            p95_power_w: percentile(&self.powers, 95.0),
+            carbon: self.carbon_g.map(|grams| Carbon::new(grams, grid_energy)),
+            cost: self
+                .cost
+                .map(|amount| Cost::new(amount, &self.currency, grid_energy)),
+            water: self.water_l.map(|litres| Water::new(litres, grid_energy)),
+        }
+    }
+
+    /// Summary of a run whose measurement failed with `error`, with the exit code of a
+    /// spawned command that had already exited, if any.
+    pub fn failed_summary(&self, pid: u32, exit_code: Option<i32>, error: String) -> Summary {
+        Summary {
+            exit_code,
+            error: Some(error),
+            ..self.summary(pid, 0)
+        }
+    }
+}
+
+/// Nearest-rank percentile of `values`, 0 when there are none.
+fn percentile(values: &[f64], p: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
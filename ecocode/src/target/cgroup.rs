@@ -0,0 +1,330 @@
+//! cgroup v2 accounting.
+//!
+//! When the command is launched inside a dedicated cgroup, the kernel accounts the CPU
+//! time of every process that ever ran in it, including short-lived children that start
+//! and exit between two samples. CPU time comes from `cpu.stat` (`usage_usec`) and the
+//! member PIDs from `cgroup.procs`, so no per-tick process scan is needed.
+//!
+//! Existing cgroups can be attached to as well: a systemd unit, a container, or a raw
+//! cgroup path is resolved to its directory in the hierarchy, and all of its member
+//! processes are measured as one entity.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+/// Default cgroup v2 mount point.
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Prefixes container runtimes give the cgroup of a container (systemd driver).
+const CONTAINER_SCOPE_PREFIXES: &[&str] = &["docker-", "libpod-", "cri-containerd-", "crio-"];
+
+/// A cgroup v2 directory.
+pub struct Cgroup {
+    path: PathBuf,
+    /// Created by EcoCode, and removed again when dropped.
+    owned: bool,
+}
+
+impl Cgroup {
+    /// Creates a fresh cgroup named after this EcoCode process below `parent`.
+    pub fn create(parent: &Path) -> io::Result<Cgroup> {
+        if !parent.join("cgroup.controllers").exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a cgroup v2 hierarchy", parent.display()),
+            ));
+        }
+        let path = parent.join(format!("ecocode-{}", process::id()));
+        fs::create_dir(&path)?;
+        Ok(Cgroup { path, owned: true })
+    }
+
+    /// Opens an existing cgroup; it is left in place when dropped.
+    pub fn open(path: &Path) -> io::Result<Cgroup> {
+        if !path.join("cgroup.procs").exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a cgroup", path.display()),
+            ));
+        }
+        Ok(Cgroup {
+            path: path.to_path_buf(),
+            owned: false,
+        })
+    }
+
+    /// Opens the cgroup at `path`, either absolute or relative to `root`.
+    pub fn at(root: &Path, path: &Path) -> io::Result<Cgroup> {
+        if path.starts_with(root) {
+            Cgroup::open(path)
+        } else {
+            // `join` would discard `root` for an absolute path like /system.slice
+            Cgroup::open(&root.join(path.strip_prefix("/").unwrap_or(path)))
+        }
+    }
+
+    /// Finds the cgroup systemd created for `unit` anywhere in the hierarchy. A name
+    /// without a type suffix is taken to be a service.
+    pub fn for_unit(root: &Path, unit: &str) -> io::Result<Cgroup> {
+        let unit = if unit.contains('.') {
+            unit.to_string()
+        } else {
+            format!("{}.service", unit)
+        };
+        find_dir(root, &unit)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no cgroup for unit {} below {}", unit, root.display()),
+                )
+            })
+            .and_then(|path| Cgroup::open(&path))
+    }
+
+    /// Finds the cgroup of the container whose ID starts with `id`, from the cgroup
+    /// membership of running processes in `/proc/*/cgroup`. No container daemon is
+    /// contacted.
+    pub fn for_container(root: &Path, id: &str) -> io::Result<Cgroup> {
+        let not_found = || {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no running container with ID {}", id),
+            )
+        };
+        if id.is_empty() {
+            return Err(not_found());
+        }
+        for entry in fs::read_dir("/proc")?.flatten() {
+            let Ok(membership) = fs::read_to_string(entry.path().join("cgroup")) else {
+                continue;
+            };
+            // cgroup v2 entry: "0::/system.slice/docker-<id>.scope"
+            let Some(path) = membership.lines().find_map(|l| l.strip_prefix("0::")) else {
+                continue;
+            };
+            let mut prefix = PathBuf::new();
+            for component in path.trim_start_matches('/').split('/') {
+                prefix.push(component);
+                if container_id(component).is_some_and(|c| c.starts_with(id)) {
+                    return Cgroup::open(&root.join(&prefix));
+                }
+            }
+        }
+        Err(not_found())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Makes `command` move itself into this cgroup right before `exec`, so that the
+    /// target and all of its descendants are accounted from their very first cycle.
+    pub fn spawn_into(&self, command: &mut Command) -> io::Result<()> {
+        let procs = OpenOptions::new()
+            .write(true)
+            .open(self.path.join("cgroup.procs"))?;
+        // SAFETY: the closure only issues a write(2) on an already open descriptor,
+        // which is async-signal-safe, between fork and exec.
+        unsafe {
+            command.pre_exec(move || attach_self(&procs));
+        }
+        Ok(())
+    }
+
+    /// PIDs of the processes currently in the cgroup.
+    pub fn pids(&self) -> io::Result<Vec<u32>> {
+        Ok(fs::read_to_string(self.path.join("cgroup.procs"))?
+            .lines()
+            .filter_map(|l| l.trim().parse().ok())
+            .collect())
+    }
+
+    /// Whether any process is left in the cgroup or its descendants. A removed cgroup
+    /// counts as empty.
+    pub fn is_populated(&self) -> bool {
+        fs::read_to_string(self.path.join("cgroup.events"))
+            .map(|events| events.lines().any(|l| l == "populated 1"))
+            .unwrap_or(false)
+    }
+
+    /// Total CPU time consumed by the cgroup's processes so far, in microseconds.
+    pub fn cpu_usage_usec(&self) -> io::Result<u64> {
+        let stat = fs::read_to_string(self.path.join("cpu.stat"))?;
+        stat.lines()
+            .find_map(|l| l.strip_prefix("usage_usec "))
+            .and_then(|v| v.trim().parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no usage_usec in cpu.stat"))
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // Only succeeds once the cgroup is empty; processes that outlived the target
+        // keep it alive and it is left in place for them.
+        if self.owned && fs::remove_dir(&self.path).is_err() {
+            eprintln!(
+                "cgroup {} still has processes, leaving it in place",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Extracts the container ID from a cgroup path component such as
+/// `docker-<id>.scope` (systemd driver) or `<id>` (cgroupfs driver).
+fn container_id(component: &str) -> Option<&str> {
+    let trimmed = component.strip_suffix(".scope").unwrap_or(component);
+    let id = CONTAINER_SCOPE_PREFIXES
+        .iter()
+        .find_map(|p| trimmed.strip_prefix(p))
+        .unwrap_or(trimmed);
+    (id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit())).then_some(id)
+}
+
+/// Breadth-first search for a directory called `name` below `root`.
+fn find_dir(root: &Path, name: &str) -> Option<PathBuf> {
+    let mut queue = vec![root.to_path_buf()];
+    let mut next = 0;
+    while next < queue.len() {
+        let Ok(entries) = fs::read_dir(&queue[next]) else {
+            next += 1;
+            continue;
+        };
+        for entry in entries.flatten() {
+            if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            if entry.file_name() == name {
+                return Some(entry.path());
+            }
+            queue.push(entry.path());
+        }
+        next += 1;
+    }
+    None
+}
+
+/// Writing `0` to `cgroup.procs` moves the writing process.
+fn attach_self(mut procs: &File) -> io::Result<()> {
+    procs.write_all(b"0")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fake cgroup directory with the given interface files.
+    fn fake_cgroup(path: &Path, files: &[(&str, &str)]) {
+        fs::create_dir_all(path).unwrap();
+        fs::write(path.join("cgroup.procs"), "").unwrap();
+        for (name, contents) in files {
+            fs::write(path.join(name), contents).unwrap();
+        }
+    }
+
+    #[test]
+    fn creates_and_removes_its_own_cgroup() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(Cgroup::create(root.path()).is_err());
+
+        fs::write(root.path().join("cgroup.controllers"), "cpu memory\n").unwrap();
+        let cgroup = Cgroup::create(root.path()).unwrap();
+        let path = cgroup.path().to_path_buf();
+        assert_eq!(path, root.path().join(format!("ecocode-{}", process::id())));
+        assert!(path.is_dir());
+        drop(cgroup);
+        assert!(!path.exists());
+
+        // An opened cgroup is not ours to remove
+        fake_cgroup(&path, &[]);
+        drop(Cgroup::open(&path).unwrap());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn reads_cpu_usage_members_and_population() {
+        let root = tempfile::tempdir().unwrap();
+        fake_cgroup(
+            root.path(),
+            &[
+                ("cpu.stat", "usage_usec 1234567\nuser_usec 1000000\n"),
+                ("cgroup.events", "populated 1\nfrozen 0\n"),
+            ],
+        );
+        fs::write(root.path().join("cgroup.procs"), "42\n43\n").unwrap();
+        let cgroup = Cgroup::open(root.path()).unwrap();
+        assert_eq!(cgroup.cpu_usage_usec().unwrap(), 1_234_567);
+        assert_eq!(cgroup.pids().unwrap(), vec![42, 43]);
+        assert!(cgroup.is_populated());
+
+        fs::write(root.path().join("cgroup.events"), "populated 0\nfrozen 0\n").unwrap();
+        assert!(!cgroup.is_populated());
+        fs::remove_file(root.path().join("cgroup.events")).unwrap();
+        assert!(!cgroup.is_populated());
+
+        fs::write(root.path().join("cpu.stat"), "user_usec 1000000\n").unwrap();
+        let error = cgroup.cpu_usage_usec().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    const ID: &str = "4f2a9c1e8b7d6a5f4e3d2c1b0a9f8e7d6c5b4a3f2e1d0c9b8a7f6e5d4c3b2a1f";
+
+    #[test]
+    fn extracts_container_ids_from_path_components() {
+        for component in [
+            format!("docker-{}.scope", ID),
+            format!("libpod-{}.scope", ID),
+            format!("cri-containerd-{}.scope", ID),
+            ID.to_string(),
+        ] {
+            assert_eq!(container_id(&component), Some(ID), "{}", component);
+        }
+        assert_eq!(container_id("system.slice"), None);
+        assert_eq!(container_id("docker-4f2a9c1e.scope"), None);
+        assert_eq!(container_id(&format!("docker-{}x.scope", &ID[1..])), None);
+    }
+
+    #[test]
+    fn finds_unit_directories_breadth_first() {
+        let root = tempfile::tempdir().unwrap();
+        let shallow = root.path().join("system.slice/nginx.service");
+        let deep = root
+            .path()
+            .join("user.slice/user-1000.slice/a/nginx.service");
+        fake_cgroup(&deep, &[]);
+        assert_eq!(find_dir(root.path(), "nginx.service"), Some(deep.clone()));
+        fake_cgroup(&shallow, &[]);
+        assert_eq!(
+            find_dir(root.path(), "nginx.service"),
+            Some(shallow.clone())
+        );
+        assert_eq!(find_dir(root.path(), "missing.service"), None);
+
+        // Files are not units
+        fs::write(root.path().join("cron.service"), "").unwrap();
+        assert!(Cgroup::for_unit(root.path(), "cron").is_err());
+        let unit = Cgroup::for_unit(root.path(), "nginx").unwrap();
+        assert_eq!(unit.path(), shallow);
+    }
+
+    #[test]
+    fn opens_paths_relative_to_the_root() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("system.slice/job.scope");
+        fake_cgroup(&path, &[]);
+        for given in [
+            PathBuf::from("/system.slice/job.scope"),
+            PathBuf::from("system.slice/job.scope"),
+            path.clone(),
+        ] {
+            assert_eq!(Cgroup::at(root.path(), &given).unwrap().path(), path);
+        }
+        // A directory without cgroup.procs is not a cgroup
+        fs::create_dir(root.path().join("plain")).unwrap();
+        let error = Cgroup::at(root.path(), Path::new("/plain")).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+}
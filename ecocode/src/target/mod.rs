@@ -0,0 +1,381 @@
+//! Target module — tracks the monitored processes and their lifetime.
+//!
+//! The target is either a command spawned by EcoCode, processes that are already
+//! running (attached by PID or name), or an existing cgroup (a systemd unit, a container
+//! or a raw cgroup path). A [`Tracker`] finds the target's member processes and their
+//! CPU share on every tick, either by walking the process tree or by reading the
+//! accounting of a cgroup. Exit is detected from the kernel's view of the process (a zombie waiting to be
+//! reaped) rather than from its resource usage, so workloads that idle for an interval
+//! (waiting on I/O, sleeping servers) keep being measured until they really exit.
+
+pub mod cgroup;
+pub mod tree;
+
+use crate::shutdown::Shutdown;
+use cgroup::Cgroup;
+use std::fs;
+use std::io;
+use std::process::{Child, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+use sysinfo::{Pid, ProcessesToUpdate, System};
+
+/// How the member processes of the target are found.
+pub enum Tracker {
+    /// Processes and all of their descendants, found through parent links.
+    Tree { roots: Vec<Pid> },
+    /// Every process in a cgroup, with CPU time from the cgroup's accounting.
+    Cgroup {
+        cgroup: Cgroup,
+        last_usage_usec: u64,
+    },
+}
+
+/// The target's processes during one sample.
+pub struct Members {
+    pub pids: Vec<u32>,
+    /// Share of the whole CPU (0-1) used by the target during the interval.
+    pub cpu_share: f64,
+    /// Per-process CPU shares; only filled for cgroups when explicitly requested.
+    pub processes: Vec<(u32, f64)>,
+}
+
+impl Tracker {
+    pub fn cgroup(cgroup: Cgroup) -> io::Result<Tracker> {
+        let last_usage_usec = cgroup.cpu_usage_usec()?;
+        Ok(Tracker::Cgroup {
+            cgroup,
+            last_usage_usec,
+        })
+    }
+
+    /// Refreshes process data and returns the target's members since the last call.
+    ///
+    /// `per_process` asks for per-process CPU shares even when the tracker does not
+    /// need them itself.
+    pub fn sample(
+        &mut self,
+        sys: &mut System,
+        elapsed_secs: f64,
+        per_process: bool,
+    ) -> io::Result<Members> {
+        let num_cores = sys.cpus().len().max(1) as f64;
+        match self {
+            Tracker::Tree { roots } => {
+                sys.refresh_processes(ProcessesToUpdate::All, true);
+                // An exited (not yet reaped) root still reports its final CPU time
+                let processes: Vec<(u32, f64)> = tree::process_tree(sys, roots)
+                    .iter()
+                    .map(|pid| (pid.as_u32(), process_share(sys, *pid, num_cores)))
+                    .collect();
+                Ok(Members {
+                    pids: processes.iter().map(|(pid, _)| *pid).collect(),
+                    cpu_share: processes.iter().map(|(_, share)| share).sum(),
+                    processes,
+                })
+            }
+            Tracker::Cgroup {
+                cgroup,
+                last_usage_usec,
+            } => {
+                let usage_usec = cgroup.cpu_usage_usec()?;
+                let delta_usec = usage_usec.saturating_sub(*last_usage_usec);
+                *last_usage_usec = usage_usec;
+
+                let pids = cgroup.pids()?;
+                let processes = if per_process {
+                    let sys_pids: Vec<Pid> = pids.iter().map(|p| Pid::from_u32(*p)).collect();
+                    sys.refresh_processes(ProcessesToUpdate::Some(&sys_pids), true);
+                    sys_pids
+                        .iter()
+                        .map(|pid| (pid.as_u32(), process_share(sys, *pid, num_cores)))
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                Ok(Members {
+                    pids,
+                    cpu_share: delta_usec as f64 / 1_000_000.0 / elapsed_secs / num_cores,
+                    processes,
+                })
+            }
+        }
+    }
+}
+
+/// Share of the whole CPU (0-1) used by one process since the previous refresh.
+fn process_share(sys: &System, pid: Pid, num_cores: f64) -> f64 {
+    // cpu_usage() returns total % across all cores; divide by num_cores
+    // to get a normalized value for the whole system
+    sys.process(pid)
+        .map(|process| process.cpu_usage() as f64 / num_cores / 100.0)
+        .unwrap_or(0.0)
+}
+
+/// How often the target is polled for exit while waiting for the next sample.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What ends the measurement.
+pub enum Lifetime {
+    /// A command spawned by EcoCode; its exit status is propagated.
+    Child(Child),
+    /// Already running processes, measured until all of them have exited.
+    Attached(Vec<u32>),
+    /// An existing cgroup, measured until it has no processes left. `pid` is one of its
+    /// members at attach time.
+    Cgroup { cgroup: Cgroup, pid: u32 },
+}
+
+impl Lifetime {
+    /// PID reported as the target's root in records.
+    pub fn pid(&self) -> u32 {
+        match self {
+            Lifetime::Child(child) => child.id(),
+            Lifetime::Attached(pids) => pids[0],
+            Lifetime::Cgroup { pid, .. } => *pid,
+        }
+    }
+
+    /// Root PIDs of the process trees to measure.
+    pub fn roots(&self) -> Vec<Pid> {
+        match self {
+            Lifetime::Child(child) => vec![Pid::from_u32(child.id())],
+            Lifetime::Attached(pids) => pids.iter().map(|p| Pid::from_u32(*p)).collect(),
+            Lifetime::Cgroup { pid, .. } => vec![Pid::from_u32(*pid)],
+        }
+    }
+
+    /// Sleeps for `interval`, returning early with `true` if the target exits meanwhile.
+    /// Also returns early, with `false`, when a shutdown is requested.
+    ///
+    /// On Linux a child is not reaped, so its final CPU time can still be read from
+    /// `/proc` for the last (partial) sample; call [`Lifetime::finish`] afterwards to
+    /// collect the exit status.
+    pub fn wait(&mut self, interval: Duration, shutdown: &Shutdown) -> bool {
+        let deadline = Instant::now() + interval;
+        loop {
+            if self.has_exited() {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline || shutdown.requested() {
+                return false;
+            }
+            thread::sleep(EXIT_POLL_INTERVAL.min(deadline - now));
+        }
+    }
+
+    /// Whether the target has terminated, without reaping a child when `/proc` is
+    /// available.
+    fn has_exited(&mut self) -> bool {
+        match self {
+            Lifetime::Child(child) => match process_state(child.id()) {
+                Ok(state) => state == 'Z' || state == 'X',
+                // No procfs: fall back to reaping, std keeps the status for a later `wait()`
+                Err(_) => matches!(child.try_wait(), Ok(Some(_))),
+            },
+            // Processes that are gone, or dead but not yet reaped by their parent
+            Lifetime::Attached(pids) => pids.iter().all(
+                |pid| !matches!(process_state(*pid), Ok(state) if state != 'Z' && state != 'X'),
+            ),
+            Lifetime::Cgroup { cgroup, .. } => !cgroup.is_populated(),
+        }
+    }
+
+    /// Sends `signal` to a spawned child; running processes that were attached to are
+    /// not ours to signal.
+    pub fn signal(&self, signal: i32) -> io::Result<()> {
+        if let Lifetime::Child(child) = self {
+            // SAFETY: kill(2) has no memory safety requirements.
+            if unsafe { libc::kill(child.id() as libc::pid_t, signal) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_child(&self) -> bool {
+        matches!(self, Lifetime::Child(_))
+    }
+
+    /// Stops measuring because EcoCode received `signal`, without waiting for a spawned
+    /// child, and returns the code EcoCode should exit with.
+    pub fn abandon(&mut self, signal: i32) -> io::Result<i32> {
+        match self {
+            Lifetime::Child(child) => {
+                eprintln!("Stopped monitoring process {}", child.id());
+                Ok(128 + signal)
+            }
+            _ => self.finish(),
+        }
+    }
+
+    /// Reaps a spawned child that has already exited, without waiting, and returns its
+    /// exit code. None while it still runs, and for attached processes and cgroups,
+    /// which are not ours to wait for.
+    pub fn try_finish(&mut self) -> Option<i32> {
+        match self {
+            Lifetime::Child(child) => child.try_wait().ok().flatten().map(|s| exit_code(&s)),
+            _ => None,
+        }
+    }
+
+    /// Reaps a spawned child and returns the code EcoCode should exit with.
+    ///
+    /// Attached processes and cgroups are not ours to wait for, so their runs exit
+    /// with 0.
+    pub fn finish(&mut self) -> io::Result<i32> {
+        match self {
+            Lifetime::Child(child) => {
+                let status = child.wait()?;
+                eprintln!("Process {} finished ({})", child.id(), status);
+                Ok(exit_code(&status))
+            }
+            Lifetime::Attached(pids) => {
+                eprintln!("Stopped monitoring {:?}", pids);
+                Ok(0)
+            }
+            Lifetime::Cgroup { cgroup, .. } => {
+                eprintln!("Stopped monitoring cgroup {}", cgroup.path().display());
+                Ok(0)
+            }
+        }
+    }
+}
+
+/// Finds running processes whose name contains `pattern`, excluding EcoCode itself.
+pub fn find_by_name(sys: &System, pattern: &str) -> Vec<Pid> {
+    let own_pid = Pid::from_u32(std::process::id());
+    let mut pids: Vec<Pid> = sys
+        .processes()
+        .iter()
+        .filter(|(pid, process)| {
+            **pid != own_pid
+                && process.thread_kind().is_none()
+                && process.name().to_string_lossy().contains(pattern)
+        })
+        .map(|(pid, _)| *pid)
+        .collect();
+    pids.sort();
+    pids
+}
+
+/// Reads the state letter (R, S, Z, ...) from `/proc/<pid>/stat`.
+fn process_state(pid: u32) -> io::Result<char> {
+    parse_state(&fs::read_to_string(format!("/proc/{}/stat", pid))?)
+}
+
+/// Extracts the state letter from the contents of a `/proc/<pid>/stat` file.
+fn parse_state(stat: &str) -> io::Result<char> {
+    // The command name is in parentheses and may contain spaces, so parse from the
+    // last closing parenthesis.
+    stat.rsplit_once(')')
+        .and_then(|(_, rest)| rest.trim_start().chars().next())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed stat line"))
+}
+
+/// Maps a child's exit status to the code EcoCode itself should exit with.
+///
+/// Follows the shell convention of `128 + signal` for processes killed by a signal.
+fn exit_code(status: &ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn spawn(script: &str) -> Child {
+        Command::new("sh").args(["-c", script]).spawn().unwrap()
+    }
+
+    #[test]
+    fn parses_the_state_after_the_command_name() {
+        assert_eq!(parse_state("42 (sh) S 1 42 42").unwrap(), 'S');
+        // The name may hold spaces and parentheses
+        assert_eq!(parse_state("42 (a) b (c)) Z 1 42 42").unwrap(), 'Z');
+        assert!(parse_state("42 (sh").is_err());
+        assert!(parse_state("42 (sh)").is_err());
+    }
+
+    #[test]
+    fn an_exited_child_is_a_zombie_until_reaped() {
+        assert_ne!(process_state(std::process::id()).unwrap(), 'Z');
+        let mut child = spawn("exit 3");
+        let pid = child.id();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while process_state(pid).unwrap() != 'Z' {
+            assert!(Instant::now() < deadline, "child {} did not exit", pid);
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(exit_code(&child.wait().unwrap()), 3);
+        assert!(process_state(pid).is_err());
+    }
+
+    #[test]
+    fn killed_children_exit_with_128_plus_the_signal() {
+        let mut child = spawn("kill -TERM $$");
+        assert_eq!(exit_code(&child.wait().unwrap()), 128 + libc::SIGTERM);
+
+        let mut lifetime = Lifetime::Child(spawn("exec sleep 10"));
+        lifetime.signal(libc::SIGKILL).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !lifetime.has_exited() {
+            assert!(Instant::now() < deadline, "child did not exit");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(lifetime.finish().unwrap(), 128 + libc::SIGKILL);
+    }
+
+    #[test]
+    fn try_finish_only_reaps_a_child_that_exited() {
+        let mut lifetime = Lifetime::Child(spawn("exec sleep 10"));
+        assert_eq!(lifetime.try_finish(), None);
+        lifetime.signal(libc::SIGKILL).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !lifetime.has_exited() {
+            assert!(Instant::now() < deadline, "child did not exit");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(lifetime.try_finish(), Some(128 + libc::SIGKILL));
+        assert_eq!(Lifetime::Attached(vec![u32::MAX]).try_finish(), None);
+    }
+
+    #[test]
+    fn abandoning_a_child_exits_with_the_received_signal() {
+        let mut lifetime = Lifetime::Child(spawn("exec sleep 10"));
+        assert_eq!(lifetime.abandon(libc::SIGINT).unwrap(), 128 + libc::SIGINT);
+        // The child is left running
+        assert!(!lifetime.has_exited());
+        lifetime.signal(libc::SIGKILL).unwrap();
+        lifetime.finish().unwrap();
+
+        let mut attached = Lifetime::Attached(vec![u32::MAX]);
+        assert!(attached.has_exited());
+        assert_eq!(attached.abandon(libc::SIGTERM).unwrap(), 0);
+    }
+
+    #[test]
+    fn a_cgroup_ends_once_it_is_no_longer_populated() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("cgroup.procs"), "42\n").unwrap();
+        fs::write(dir.path().join("cgroup.events"), "populated 1\nfrozen 0\n").unwrap();
+        let cgroup = Cgroup::open(dir.path()).unwrap();
+        let mut lifetime = Lifetime::Cgroup { cgroup, pid: 42 };
+        assert!(!lifetime.has_exited());
+        fs::write(dir.path().join("cgroup.events"), "populated 0\nfrozen 0\n").unwrap();
+        assert!(lifetime.has_exited());
+        assert_eq!(lifetime.finish().unwrap(), 0);
+    }
+}
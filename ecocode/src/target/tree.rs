@@ -0,0 +1,35 @@
+//! Process tree discovery.
+//!
+//! Descendants are found on every tick through the parent links sysinfo reads from
+//! `/proc/*/stat`, so workers spawned mid-run (DataLoader workers, `make -j` jobs,
+//! shell pipelines) are picked up as soon as they appear.
+
+use std::collections::{HashMap, HashSet};
+use sysinfo::{Pid, System};
+
+/// Returns `roots` followed by all of their live descendants, each process once.
+///
+/// Threads are skipped: their CPU time is already included in their process.
+pub fn process_tree(sys: &System, roots: &[Pid]) -> Vec<Pid> {
+    let mut children: HashMap<Pid, Vec<Pid>> = HashMap::new();
+    for (pid, process) in sys.processes() {
+        if process.thread_kind().is_some() {
+            continue;
+        }
+        if let Some(parent) = process.parent() {
+            children.entry(parent).or_default().push(*pid);
+        }
+    }
+
+    // Roots may be related (e.g. attaching by name matches a parent and its child)
+    let mut seen: HashSet<Pid> = HashSet::new();
+    let mut tree: Vec<Pid> = roots.iter().copied().filter(|p| seen.insert(*p)).collect();
+    let mut next = 0;
+    while next < tree.len() {
+        if let Some(kids) = children.get(&tree[next]) {
+            tree.extend(kids.iter().copied().filter(|p| seen.insert(*p)));
+        }
+        next += 1;
+    }
+    tree
+}
@@ -0,0 +1,214 @@
+//! Water footprint of the attributed energy.
+//!
+//! Water is consumed on site, by the cooling of the data centre, and off site, by the
+//! power plants generating its electricity:
+//!
+//! ```text
+//! water = IT energy × WUE + grid energy × generation water intensity
+//! ```
+//!
+//! The WUE (water usage effectiveness, L/kWh) is defined over the energy of the IT
+//! equipment, i.e. the estimated wall energy or else the measured CPU + GPU energy, while
+//! the generation water intensity applies to everything the facility draws from the grid.
+//! Both take a measured factor or a region code: the WUE of a region depends on its
+//! climate, how often the cooling must evaporate water rather than use outside air.
+
+use crate::exporter::Record;
+use crate::footprint::{JOULES_PER_KWH, check};
+
+/// Water consumed by electricity generation, in L/kWh, by ISO 3166 country code or
+/// `COUNTRY-REGION` code. Rough estimates from the generation mix and typical consumption
+/// factors per technology (cooling of thermal and nuclear plants, hydropower reservoir
+/// evaporation left out); use a measured factor where accuracy matters.
+pub const REGIONS: &[(&str, f64)] = &[
+    ("WORLD", 1.7),
+    ("EU", 1.3),
+    ("AU", 1.6),
+    ("BR", 0.4),
+    ("CA", 0.5),
+    ("CH", 1.0),
+    ("CN", 1.8),
+    ("DE", 1.2),
+    ("DK", 0.3),
+    ("ES", 0.9),
+    ("FI", 1.1),
+    ("FR", 2.0),
+    ("GB", 0.8),
+    ("IE", 0.6),
+    ("IN", 2.3),
+    ("IT", 0.8),
+    ("JP", 1.3),
+    ("KR", 1.9),
+    ("NL", 0.8),
+    ("NO", 0.1),
+    ("PL", 2.0),
+    ("SE", 1.1),
+    ("SG", 0.7),
+    ("US", 1.9),
+    ("US-CA", 0.7),
+    ("US-TX", 1.4),
+    ("US-VA", 1.8),
+    ("US-WA", 0.4),
+    ("ZA", 2.0),
+];
+
+/// On-site water usage effectiveness of data centres, in L/kWh of IT energy, by ISO 3166
+/// country code or `COUNTRY-REGION` code. Rough estimates for evaporatively cooled sites
+/// from the climate, around the 1.8 L/kWh US average reported by LBNL in 2016; use the
+/// operator's published WUE where it is known, and 0 for a dry-cooled site.
+pub const WUE_REGIONS: &[(&str, f64)] = &[
+    ("WORLD", 1.8),
+    ("EU", 0.6),
+    ("AU", 2.0),
+    ("BR", 2.0),
+    ("CA", 0.7),
+    ("CH", 0.5),
+    ("CN", 1.8),
+    ("DE", 0.5),
+    ("DK", 0.2),
+    ("ES", 1.2),
+    ("FI", 0.2),
+    ("FR", 0.7),
+    ("GB", 0.4),
+    ("IE", 0.2),
+    ("IN", 2.5),
+    ("IT", 1.0),
+    ("JP", 1.4),
+    ("KR", 1.4),
+    ("NL", 0.4),
+    ("NO", 0.1),
+    ("PL", 0.5),
+    ("SE", 0.2),
+    ("SG", 2.4),
+    ("US", 1.8),
+    ("US-AZ", 2.8),
+    ("US-CA", 1.6),
+    ("US-TX", 2.2),
+    ("US-VA", 1.6),
+    ("US-WA", 0.6),
+    ("ZA", 2.2),
+];
+
+/// Water consumption factors of the run.
+#[derive(Clone, Copy, Debug)]
+pub struct WaterFactors {
+    /// On-site water per kWh of IT energy.
+    wue_l_per_kwh: f64,
+    /// Off-site water per kWh drawn from the grid.
+    intensity_l_per_kwh: f64,
+}
+
+impl WaterFactors {
+    /// The factors of `--wue` and `--water-intensity`; an absent one counts no water.
+    pub fn new(wue: Option<f64>, intensity: Option<f64>) -> WaterFactors {
+        WaterFactors {
+            wue_l_per_kwh: wue.unwrap_or(0.0),
+            intensity_l_per_kwh: intensity.unwrap_or(0.0),
+        }
+    }
+
+    /// Sets the water consumed for the energy of `record`.
+    pub fn apply(&self, record: &mut Record) {
+        let it_kwh = record.wall_energy_j().unwrap_or(record.energy_j()) / JOULES_PER_KWH;
+        let grid_kwh = record.grid_energy_j() / JOULES_PER_KWH;
+        record.water_l = Some(it_kwh * self.wue_l_per_kwh + grid_kwh * self.intensity_l_per_kwh);
+    }
+}
+
+/// Parses `--wue`: litres per kWh of IT energy or a region code of [`WUE_REGIONS`].
+pub fn parse_wue(spec: &str) -> Result<f64, String> {
+    parse_factor("WUE", WUE_REGIONS, spec)
+}
+
+/// Parses `--water-intensity`: litres per kWh or a region code of [`REGIONS`].
+pub fn parse_intensity(spec: &str) -> Result<f64, String> {
+    parse_factor("water intensity", REGIONS, spec)
+}
+
+fn parse_factor(what: &str, regions: &[(&str, f64)], spec: &str) -> Result<f64, String> {
+    if let Ok(value) = spec.parse::<f64>() {
+        return check(what, value);
+    }
+    regions
+        .iter()
+        .find(|(code, _)| code.eq_ignore_ascii_case(spec))
+        .map(|(_, value)| *value)
+        .ok_or_else(|| {
+            format!(
+                "{} is neither L/kWh nor a known region code (one of {})",
+                spec,
+                regions
+                    .iter()
+                    .map(|(code, _)| *code)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::testing::record;
+
+    // 3.6 MJ is one kWh
+    fn sample(cpu_w: f64, gpu_w: Option<f64>) -> Record {
+        Record {
+            interval_secs: 3600.0,
+            cpu_power_w: cpu_w,
+            gpu_power_w: gpu_w,
+            ..record(1)
+        }
+    }
+
+    #[test]
+    fn measured_energy_is_both_it_and_grid_energy() {
+        let factors = WaterFactors::new(Some(1.8), Some(0.5));
+        let mut record = sample(600.0, Some(400.0));
+        factors.apply(&mut record);
+        assert!((record.water_l.unwrap() - (1.8 + 0.5)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn wue_applies_to_wall_energy_and_intensity_to_facility_energy() {
+        let factors = WaterFactors::new(Some(2.0), Some(1.0));
+        let mut record = Record {
+            wall_power_w: Some(2000.0),
+            facility_power_w: Some(3000.0),
+            ..sample(600.0, Some(400.0))
+        };
+        factors.apply(&mut record);
+        assert!((record.water_l.unwrap() - (2.0 * 2.0 + 3.0 * 1.0)).abs() < 1e-9);
+
+        // without a wall estimate the WUE applies to CPU + GPU energy
+        let mut record = Record {
+            facility_power_w: Some(3000.0),
+            ..sample(1000.0, None)
+        };
+        factors.apply(&mut record);
+        assert!((record.water_l.unwrap() - (2.0 + 3.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn an_absent_factor_counts_no_water() {
+        let mut record = sample(1000.0, None);
+        WaterFactors::new(None, Some(1.5)).apply(&mut record);
+        assert!((record.water_l.unwrap() - 1.5).abs() < 1e-9);
+        WaterFactors::new(None, None).apply(&mut record);
+        assert_eq!(record.water_l, Some(0.0));
+    }
+
+    #[test]
+    fn parses_factors_and_region_codes() {
+        assert_eq!(parse_wue("1.2"), Ok(1.2));
+        assert_eq!(parse_wue("0"), Ok(0.0));
+        assert_eq!(parse_wue("us-az"), Ok(2.8));
+        assert_eq!(parse_intensity("NO"), Ok(0.1));
+        assert_eq!(parse_wue("-1"), Err("WUE -1 is negative".to_string()));
+        assert!(
+            parse_intensity("XX")
+                .unwrap_err()
+                .starts_with("XX is neither L/kWh")
+        );
+    }
+}